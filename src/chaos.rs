@@ -0,0 +1,77 @@
+//! Failure injection for testing how clients handle the service misbehaving.
+
+use std::{sync::OnceLock, time::Duration};
+
+use rand::Rng;
+
+use crate::{parse_env, Error};
+
+struct Config {
+    delay_rate: f64,
+    delay: Duration,
+    error_rate: f64,
+    truncate_rate: f64,
+}
+
+fn read_rate(key: &str) -> f64 {
+    parse_env(key).unwrap_or(0.0_f64).clamp(0.0, 1.0)
+}
+
+fn get_config() -> Option<&'static Config> {
+    static CONFIG: OnceLock<Option<Config>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let config = Config {
+                delay_rate: read_rate("CHAOS_DELAY_RATE"),
+                delay: Duration::from_millis(parse_env("CHAOS_DELAY_MS").unwrap_or(5000)),
+                error_rate: read_rate("CHAOS_ERROR_RATE"),
+                truncate_rate: read_rate("CHAOS_TRUNCATE_RATE"),
+            };
+
+            if config.delay_rate == 0.0 && config.error_rate == 0.0 && config.truncate_rate == 0.0 {
+                return None;
+            }
+
+            tracing::warn!("Failure injection is enabled, this should not be used in production!");
+            Some(config)
+        })
+        .as_ref()
+}
+
+/// Randomly delays the request by `CHAOS_DELAY_MS`, then randomly fails it with a 503.
+pub async fn inject_failure() -> Result<(), Error> {
+    let Some(config) = get_config() else {
+        return Ok(());
+    };
+
+    let (delay, error) = {
+        let mut rng = rand::thread_rng();
+        (
+            rng.gen_bool(config.delay_rate),
+            rng.gen_bool(config.error_rate),
+        )
+    };
+
+    if delay {
+        tracing::debug!("Injecting a {:?} delay", config.delay);
+        tokio::time::sleep(config.delay).await;
+    }
+
+    if error {
+        tracing::debug!("Injecting an error response");
+        return Err(Error::Overloaded);
+    }
+
+    Ok(())
+}
+
+/// Randomly cuts the audio in half, simulating a malformed response.
+pub fn truncate_audio(audio: bytes::Bytes) -> bytes::Bytes {
+    match get_config() {
+        Some(config) if rand::thread_rng().gen_bool(config.truncate_rate) => {
+            tracing::debug!("Injecting truncated audio");
+            audio.slice(..audio.len() / 2)
+        }
+        _ => audio,
+    }
+}
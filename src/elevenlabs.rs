@@ -0,0 +1,199 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use small_fixed_array::FixedString;
+
+use crate::{str_to_fixedstring, voice_cache::VoiceCache, wav, QuotaExceeded, RateLimited, Result};
+
+const API_BASE: &str = "https://api.elevenlabs.io/";
+const DEFAULT_MODEL_ID: &str = "eleven_multilingual_v2";
+const DEFAULT_OUTPUT_FORMAT: &str = "mp3_44100_128";
+pub const OUTPUT_FORMATS: &[&str] = &[
+    "mp3_22050_32",
+    "mp3_44100_64",
+    "mp3_44100_96",
+    "mp3_44100_128",
+    "mp3_44100_192",
+    "pcm_16000",
+    "pcm_22050",
+    "pcm_24000",
+    "pcm_44100",
+];
+
+pub struct State {
+    reqwest: reqwest::Client,
+    api_key: Option<FixedString>,
+    model_id: String,
+}
+
+impl State {
+    pub fn new(reqwest: reqwest::Client) -> Self {
+        let api_key = std::env::var("ELEVENLABS_API_KEY").ok();
+        if api_key.is_none() {
+            tracing::warn!("ELEVENLABS_API_KEY not set, ElevenLabs is disabled");
+        }
+
+        Self {
+            reqwest,
+            api_key: api_key.map(str_to_fixedstring),
+            model_id: std::env::var("ELEVENLABS_MODEL_ID")
+                .unwrap_or_else(|_| String::from(DEFAULT_MODEL_ID)),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.api_key.is_some()
+    }
+
+    fn api_key(&self) -> Result<&str> {
+        match self.api_key.as_deref() {
+            Some(api_key) => Ok(api_key),
+            None => anyhow::bail!("ElevenLabs has not been configured"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct VerifiedLanguage {
+    pub language: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct ElevenLabsVoice {
+    pub voice_id: String,
+    pub name: String,
+    #[serde(default)]
+    pub verified_languages: Vec<VerifiedLanguage>,
+}
+
+pub fn check_format(format: &str) -> bool {
+    OUTPUT_FORMATS
+        .iter()
+        .any(|f| f.eq_ignore_ascii_case(format))
+}
+
+/// The body of errors from the API, such as `{"detail": {"status": "quota_exceeded"}}`.
+#[derive(serde::Deserialize)]
+struct ErrorResponse {
+    detail: ErrorDetail,
+}
+
+#[derive(serde::Deserialize)]
+struct ErrorDetail {
+    status: String,
+}
+
+/// The API returns 429 for its concurrency and rate limits, which are temporary,
+/// and 401 with a `quota_exceeded` status once the account's credits run out.
+async fn check_status(resp: reqwest::Response) -> Result<reqwest::Response> {
+    match resp.status() {
+        reqwest::StatusCode::TOO_MANY_REQUESTS => Err(RateLimited.into()),
+        reqwest::StatusCode::UNAUTHORIZED => {
+            let body = resp.bytes().await?;
+            match serde_json::from_slice::<ErrorResponse>(&body) {
+                Ok(ErrorResponse { detail }) if detail.status == "quota_exceeded" => {
+                    Err(QuotaExceeded.into())
+                }
+                _ => anyhow::bail!(
+                    "ElevenLabs rejected the request as unauthorized: {}",
+                    String::from_utf8_lossy(&body)
+                ),
+            }
+        }
+        _ => Ok(resp.error_for_status()?),
+    }
+}
+
+pub async fn get_tts(
+    state: &State,
+    text: &str,
+    voice: &str,
+    preferred_format: Option<&str>,
+) -> Result<(bytes::Bytes, Option<reqwest::header::HeaderValue>)> {
+    let output_format = preferred_format
+        .and_then(|pf| OUTPUT_FORMATS.iter().find(|f| f.eq_ignore_ascii_case(pf)))
+        .copied()
+        .unwrap_or(DEFAULT_OUTPUT_FORMAT);
+
+    let resp = state
+        .reqwest
+        .post(format!("{API_BASE}v1/text-to-speech/{voice}"))
+        .query(&[("output_format", output_format)])
+        .header("xi-api-key", state.api_key()?)
+        .json(&serde_json::json!({
+            "text": text,
+            "model_id": state.model_id,
+            "voice_settings": {
+                "stability": 0.5,
+                "similarity_boost": 0.75,
+            },
+        }))
+        .send()
+        .await?;
+    let audio = check_status(resp).await?.bytes().await?;
+
+    // PCM is returned as headerless signed 16-bit mono, which most players cannot handle.
+    if let Some(sample_rate) = output_format.strip_prefix("pcm_") {
+        let sample_rate = sample_rate.parse()?;
+        return Ok((
+            bytes::Bytes::from(wav::from_pcm(&audio, sample_rate, 1, 16)?),
+            Some(reqwest::header::HeaderValue::from_static("audio/wav")),
+        ));
+    }
+
+    Ok((
+        audio,
+        Some(reqwest::header::HeaderValue::from_static("audio/mpeg")),
+    ))
+}
+
+static VOICES: VoiceCache<Vec<ElevenLabsVoice>> = VoiceCache::new();
+async fn fetch_voices(state: &State) -> Result<Vec<ElevenLabsVoice>> {
+    #[derive(serde::Deserialize)]
+    struct VoiceResponse {
+        voices: Vec<ElevenLabsVoice>,
+    }
+
+    let resp = state
+        .reqwest
+        .get(format!("{API_BASE}v1/voices"))
+        .header("xi-api-key", state.api_key()?)
+        .send()
+        .await?;
+    let resp: VoiceResponse = check_status(resp).await?.json().await?;
+
+    Ok(resp.voices)
+}
+
+pub async fn check_voice(state: &State, voice: &str) -> Result<bool> {
+    Ok(get_raw_voices(state)
+        .await?
+        .iter()
+        .any(|v| v.voice_id == voice))
+}
+
+pub async fn get_raw_voices(state: &State) -> Result<Arc<Vec<ElevenLabsVoice>>> {
+    VOICES.get(|| fetch_voices(state)).await
+}
+
+pub async fn refresh_voices(state: &State) -> Result<()> {
+    VOICES.refresh(|| fetch_voices(state)).await
+}
+
+pub async fn get_voices(state: &State) -> Result<Vec<String>> {
+    Ok(get_raw_voices(state)
+        .await?
+        .iter()
+        .map(|v| v.voice_id.clone())
+        .collect())
+}
+
+pub async fn get_languages(state: &State) -> Result<Vec<String>> {
+    let voices = get_raw_voices(state).await?;
+    let languages: BTreeSet<&str> = voices
+        .iter()
+        .flat_map(|v| &v.verified_languages)
+        .map(|l| l.language.as_str())
+        .collect();
+
+    Ok(languages.into_iter().map(String::from).collect())
+}
@@ -1,11 +1,14 @@
-use std::sync::{LazyLock, OnceLock};
+use std::{
+    collections::BTreeSet,
+    sync::{LazyLock, OnceLock},
+};
 
 use aformat::{aformat, CapStr, ToArrayString};
 use memchr::memmem::Finder;
 use reqwest::header::HeaderValue;
 use tokio::io::AsyncReadExt;
 
-use crate::Result;
+use crate::{ffmpeg, language, wav, Result};
 
 struct Finders {
     replaced_with_err: Finder<'static>,
@@ -21,6 +24,8 @@ pub async fn get_tts(
     text: &str,
     voice: &str,
     speaking_rate: u16,
+    sample_rate: Option<u32>,
+    preferred_format: Option<&str>,
 ) -> Result<(bytes::Bytes, Option<HeaderValue>)> {
     if !check_voice(voice) {
         anyhow::bail!("Invalid voice: {voice}");
@@ -46,6 +51,7 @@ pub async fn get_tts(
                 &speaking_rate.to_arraystring(),
                 "-v",
                 &aformat!("mb/mb-{voice}"),
+                "--",
                 text,
             ])
             .spawn()?;
@@ -85,7 +91,7 @@ pub async fn get_tts(
 
                 tracing::debug!("mbrola_stderr watcher closed");
             });
-        };
+        }
 
         let output = mbrola_process.wait_with_output().await?;
         if output.stdout.len() == 44 {
@@ -98,20 +104,39 @@ pub async fn get_tts(
                 i += 1;
                 continue;
             }
-        };
+        }
 
         tracing::debug!("Generated eSpeak after {i} tries");
         break output.stdout;
     };
 
-    // Fix the wav header to set the ChunkSize and SubChunk2Size
-    // See:
-    // - https://github.com/hadware/voxpopuli/blob/fb94a6130c046bb9f7a27aaaed2a4b434666faa9/voxpopuli/main.py#L150-L158
-    // - http://soundfile.sapp.org/doc/WaveFormat/
-    let wav_len: u32 = raw_wav.len().try_into().expect("WAV data too long!");
+    wav::fix_header(&mut raw_wav)?;
+    if preferred_format.is_some_and(check_format) {
+        // Opus is always decoded at 48kHz, and does not support some of mbrola's native rates.
+        let opus = ffmpeg::run(&raw_wav, &["-ar", "48000"], ffmpeg::Format::OggOpus).await?;
+        return Ok((opus, Some(HeaderValue::from_static("audio/opus"))));
+    }
 
-    raw_wav[4..8].copy_from_slice(&(wav_len - 8).to_le_bytes());
-    raw_wav[40..44].copy_from_slice(&(wav_len - 44).to_le_bytes());
+    if let Some(sample_rate) = sample_rate {
+        // mbrola always outputs at the voice's native rate, so resample to the requested one.
+        let resampled = ffmpeg::run(
+            &raw_wav,
+            &[
+                "-ar",
+                &sample_rate.to_arraystring(),
+                "-map_metadata",
+                "-1",
+                "-fflags",
+                "+bitexact",
+            ],
+            ffmpeg::Format::Wav,
+        )
+        .await?;
+
+        // ffmpeg cannot seek back to fill in the sizes when writing to a pipe.
+        raw_wav = resampled.into();
+        wav::fix_header(&mut raw_wav)?;
+    }
 
     Ok((
         bytes::Bytes::from(raw_wav),
@@ -119,39 +144,104 @@ pub async fn get_tts(
     ))
 }
 
+pub const FORMATS: &[&str] = &["opus"];
+
+/// eSpeak only outputs WAV, so other formats are transcoded with ffmpeg.
+pub fn check_format(format: &str) -> bool {
+    ffmpeg::is_enabled() && FORMATS.iter().any(|f| f.eq_ignore_ascii_case(format))
+}
+
+/// Opus audio is always encoded at 48kHz, so cannot be given a sample rate.
+pub fn check_sample_rate(preferred_format: Option<&str>, sample_rate: u32) -> bool {
+    !preferred_format.is_some_and(check_format)
+        && matches!(sample_rate, 8000 | 16000 | 22050 | 24000 | 44100 | 48000)
+}
+
+pub async fn get_ipa(text: &str, voice: &str) -> Result<String> {
+    if !check_voice(voice) {
+        anyhow::bail!("Invalid voice: {voice}");
+    }
+
+    let voice = CapStr::<8>(voice);
+    let output = tokio::process::Command::new("espeak")
+        // `--` stops text starting with `-` from being parsed as options.
+        .args(["-q", "--ipa", "-v", &aformat!("mb/mb-{voice}"), "--", text])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "espeak failed to generate IPA: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let ipa = String::from_utf8(output.stdout)?;
+    Ok(ipa.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
 pub fn check_length(audio: &[u8], max_length: u32) -> bool {
-    audio.len() as u32
-        / (u16::from_le_bytes(audio[22..24].try_into().unwrap()) as u32 * // Sample Rate
-        u32::from_le_bytes(audio[24..28].try_into().unwrap()) *        // Number of Channels
-        u16::from_le_bytes(audio[34..36].try_into().unwrap()) as u32   // Bits per Sample
-        / 8)
-        < max_length
+    // The length of Opus audio cannot be checked without decoding it.
+    if audio.starts_with(b"OggS") {
+        return true;
+    }
+
+    let Some(duration) = wav::parse(audio).as_ref().and_then(wav::Wav::duration) else {
+        tracing::warn!("Cannot check the length of malformed eSpeak audio");
+        return true;
+    };
+
+    duration.as_secs() < u64::from(max_length)
 }
 
-pub fn get_voices() -> &'static [String] {
-    static VOICES: OnceLock<Vec<String>> = OnceLock::new();
-    VOICES.get_or_init(|| {
-        (|| {
-            let mut files = Vec::new();
-            for file in std::fs::read_dir("/usr/local/share/espeak-ng-data/voices/mb")? {
-                let file = file?;
-                if file.file_type()?.is_file() {
-                    let file_name = file.file_name().into_string().expect("Invalid filename!");
-                    let mut file_name_iter = file_name.split('-').skip(1);
-
-                    if let Some(language) = file_name_iter.next() {
-                        if file_name_iter.next().is_none() {
-                            files.push(language.to_owned());
-                        }
-                    }
+static VOICES: OnceLock<Vec<String>> = OnceLock::new();
+
+fn scan_voices() -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    for file in std::fs::read_dir("/usr/local/share/espeak-ng-data/voices/mb")? {
+        let file = file?;
+        if file.file_type()?.is_file() {
+            let file_name = file.file_name().into_string().expect("Invalid filename!");
+            let mut file_name_iter = file_name.split('-').skip(1);
+
+            if let Some(language) = file_name_iter.next() {
+                if file_name_iter.next().is_none() {
+                    files.push(language.to_owned());
                 }
             }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Scans for the voices on a blocking thread, so the first request does not block the runtime.
+pub async fn load_voices() {
+    match tokio::task::spawn_blocking(scan_voices).await {
+        Ok(Ok(voices)) => {
+            tracing::debug!("Loaded {} eSpeak voices", voices.len());
+            let _ = VOICES.set(voices);
+        }
+        Ok(Err(err)) => tracing::warn!("Failed to load eSpeak voices: {err}"),
+        Err(err) => tracing::warn!("Failed to load eSpeak voices: {err}"),
+    }
+}
+
+/// The voices loaded by `load_voices` at startup, or none if that failed.
+///
+/// This never scans the filesystem itself, as it is called while handling requests.
+pub fn get_voices() -> &'static [String] {
+    VOICES.get().map_or(&[], Vec::as_slice)
+}
 
-            files.sort();
-            anyhow::Ok(files)
-        })()
-        .unwrap()
-    })
+pub fn get_languages() -> Vec<String> {
+    get_voices()
+        .iter()
+        .map(|voice| language::espeak_voice_language(voice))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
 }
 
 pub fn check_voice(voice: &str) -> bool {
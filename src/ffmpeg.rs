@@ -0,0 +1,92 @@
+use std::sync::OnceLock;
+
+use crate::{subprocess, Result};
+
+fn get_path() -> Option<&'static str> {
+    static FFMPEG_PATH: OnceLock<Option<String>> = OnceLock::new();
+    FFMPEG_PATH
+        .get_or_init(|| std::env::var("FFMPEG_PATH").ok())
+        .as_deref()
+}
+
+/// If `FFMPEG_PATH` has been set, enabling audio post-processing.
+pub fn is_enabled() -> bool {
+    get_path().is_some()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Mp3,
+    Wav,
+    OggVorbis,
+    OggOpus,
+}
+
+impl Format {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        match content_type {
+            "audio/mpeg" | "audio/mp3" => Some(Self::Mp3),
+            "audio/wav" | "audio/x-wav" | "audio/wave" => Some(Self::Wav),
+            "audio/ogg" | "audio/vorbis" => Some(Self::OggVorbis),
+            "audio/opus" => Some(Self::OggOpus),
+            _ => None,
+        }
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Mp3 => "audio/mpeg",
+            Self::Wav => "audio/wav",
+            Self::OggVorbis => "audio/ogg",
+            Self::OggOpus => "audio/opus",
+        }
+    }
+
+    fn output_args(self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &["-f", "mp3"],
+            Self::Wav => &["-f", "wav"],
+            Self::OggVorbis => &["-c:a", "libvorbis", "-f", "ogg"],
+            Self::OggOpus => &["-c:a", "libopus", "-f", "opus"],
+        }
+    }
+}
+
+/// Pipes `audio` through ffmpeg with the given extra arguments, encoding the result as `output`.
+pub async fn run(audio: &[u8], args: &[&str], output: Format) -> Result<bytes::Bytes> {
+    let Some(path) = get_path() else {
+        anyhow::bail!("ffmpeg has not been configured");
+    };
+
+    let mut command = tokio::process::Command::new(path);
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0"])
+        .args(args)
+        .args(output.output_args())
+        .arg("pipe:1");
+
+    Ok(bytes::Bytes::from(subprocess::pipe(command, audio).await?))
+}
+
+/// Trims leading and trailing silence from the audio, keeping the same format.
+pub async fn trim_silence(audio: &[u8], format: Format) -> Result<bytes::Bytes> {
+    const TRIM_START: &str = "silenceremove=start_periods=1:start_threshold=-50dB";
+    let filter = format!("{TRIM_START},areverse,{TRIM_START},areverse");
+
+    run(audio, &["-af", &filter], format).await
+}
+
+/// The integrated loudness targets accepted by ffmpeg's loudnorm filter.
+pub const LOUDNESS_RANGE: std::ops::RangeInclusive<f32> = -70.0..=-5.0;
+
+/// Normalizes the integrated loudness of the audio to `target_lufs`, keeping the same format.
+pub async fn normalize_loudness(
+    audio: &[u8],
+    target_lufs: f32,
+    format: Format,
+) -> Result<bytes::Bytes> {
+    let filter = format!("loudnorm=I={target_lufs}:TP=-1.5:LRA=11");
+
+    // loudnorm upsamples to 192kHz internally, so resample to something players expect.
+    run(audio, &["-af", &filter, "-ar", "48000"], format).await
+}
@@ -1,9 +1,33 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, OnceLock},
+};
+
 use base64::Engine;
 use tokio::sync::RwLock;
 
-use crate::Result;
+use crate::{ffmpeg, ogg, text, voice_cache::VoiceCache, wav, QuotaExceeded, Result};
+
+const DEFAULT_GOOGLE_API_BASE: &str = "https://texttospeech.googleapis.com/";
+
+/// The TTS endpoint to use, configurable for regional endpoints such as `eu-texttospeech.googleapis.com`.
+fn get_api_base() -> &'static str {
+    static API_BASE: OnceLock<String> = OnceLock::new();
+    API_BASE.get_or_init(|| match std::env::var("GOOGLE_TTS_ENDPOINT") {
+        Ok(mut endpoint) => {
+            if !endpoint.contains("://") {
+                endpoint.insert_str(0, "https://");
+            }
 
-const GOOGLE_API_BASE: &str = "https://texttospeech.googleapis.com/";
+            if !endpoint.ends_with('/') {
+                endpoint.push('/');
+            }
+
+            endpoint
+        }
+        Err(_) => String::from(DEFAULT_GOOGLE_API_BASE),
+    })
+}
 
 #[derive(Clone)]
 pub struct State {
@@ -64,6 +88,35 @@ pub struct GoogleVoice {
     #[serde(default)]
     pub ssmlGender: Gender,
     pub languageCodes: [String; 1],
+    /// The quality tier of the voice, such as `Standard`, `Wavenet`, or `Chirp3`, parsed from the name.
+    #[serde(default)]
+    pub tier: String,
+}
+
+/// Voice names are `{lang}-{region}-{tier}-{variant}`, with the variant sometimes containing dashes.
+fn parse_tier(voice_name: &str) -> &str {
+    voice_name.split('-').nth(2).unwrap_or_default()
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy)]
+pub enum PhoneticEncoding {
+    #[serde(rename = "PHONETIC_ENCODING_IPA")]
+    Ipa,
+    #[serde(rename = "PHONETIC_ENCODING_X_SAMPA")]
+    XSampa,
+    #[serde(rename = "PHONETIC_ENCODING_JAPANESE_YOMIGANA")]
+    JapaneseYomigana,
+    #[serde(rename = "PHONETIC_ENCODING_PINYIN")]
+    Pinyin,
+}
+
+/// Overrides how a phrase is pronounced, without needing a custom voice.
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomPronunciation {
+    pub phrase: String,
+    pub phonetic_encoding: PhoneticEncoding,
+    pub pronunciation: String,
 }
 
 #[allow(non_camel_case_types, clippy::upper_case_acronyms)]
@@ -98,6 +151,10 @@ impl AudioEncoding {
         }
     }
 
+    fn is_wav(self) -> bool {
+        matches!(self, Self::LINEAR16 | Self::ALAW | Self::MULAW)
+    }
+
     fn content_type(self) -> &'static str {
         match self {
             Self::LINEAR16 | Self::ALAW | Self::MULAW => "audio/wav",
@@ -107,29 +164,67 @@ impl AudioEncoding {
     }
 }
 
+fn check_quota(resp: reqwest::Response) -> Result<reqwest::Response> {
+    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        Err(QuotaExceeded.into())
+    } else {
+        Ok(resp.error_for_status()?)
+    }
+}
+
 fn generate_google_json(
     content: &str,
     lang: &str,
     speaking_rate: f32,
     audio_encoding: &str,
-) -> Result<impl serde::Serialize> {
-    let (lang, variant) = lang
-        .split_once(' ')
-        .ok_or_else(|| anyhow::anyhow!("{lang} cannot be parsed into lang and variant"))?;
-
-    Ok(serde_json::json!({
-        "input": {
-            "text": content
-        },
-        "voice": {
+    digits_mode: bool,
+    custom_pronunciations: &[CustomPronunciation],
+    voice_clone_key: Option<&str>,
+) -> impl serde::Serialize {
+    let (language_code, name) = if let Some((lang, variant)) = lang.split_once(' ') {
+        (lang, format!("{lang}-Standard-{variant}"))
+    } else {
+        // Other tiers are referred to by their full name, `{lang}-{region}-{tier}-{variant}`,
+        // so the language code is everything before the second dash.
+        let language_code = lang
+            .match_indices('-')
+            .nth(1)
+            .map_or(lang, |(index, _)| &lang[..index]);
+
+        (language_code, String::from(lang))
+    };
+
+    let mut input = if digits_mode {
+        serde_json::json!({"ssml": format!("<speak>{}</speak>", text::ssml_digits(&text::escape_xml(content)))})
+    } else {
+        serde_json::json!({"text": content})
+    };
+
+    if !custom_pronunciations.is_empty() {
+        input["customPronunciations"] =
+            serde_json::json!({"pronunciations": custom_pronunciations});
+    }
+
+    // Cloned voices are only referred to by their key, `lang` is just the language code.
+    let voice = match voice_clone_key {
+        Some(voice_clone_key) => serde_json::json!({
             "languageCode": lang,
-            "name": format!("{lang}-Standard-{variant}"),
-        },
+            "voiceClone": {"voiceCloningKey": voice_clone_key},
+        }),
+        None => serde_json::json!({
+            "languageCode": language_code,
+            "name": name,
+        }),
+    };
+
+    serde_json::json!({
+        "input": input,
+        "voice": voice,
         "audioConfig": {
             "audioEncoding": audio_encoding,
             "speakingRate": speaking_rate
         }
-    }))
+    })
 }
 
 fn generate_jwt(
@@ -142,11 +237,13 @@ fn generate_jwt(
     let mut headers = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
     headers.kid = Some(private_key_raw);
 
-    let new_expire_time = current_time + std::time::Duration::from_secs(3600);
+    let new_expire_time = current_time + std::time::Duration::from_hours(1);
     let payload = serde_json::json!({
         "exp": new_expire_time.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
         "iat": current_time.duration_since(std::time::UNIX_EPOCH)?.as_secs(),
-        "aud": GOOGLE_API_BASE,
+        // Google only accepts self-signed JWTs for the service's global audience,
+        // which regional endpoints also accept.
+        "aud": DEFAULT_GOOGLE_API_BASE,
         "iss": client_email,
         "sub": client_email,
     });
@@ -167,11 +264,15 @@ async fn refresh_jwt(state: &RwLock<State>) -> Result<String> {
     };
 
     if current_time > expire_time {
-        let (jwt_token, new_expire_time) = generate_jwt(
-            service_account.private_key.clone(),
-            &service_account.client_email,
-            current_time,
-        )?;
+        // Signing is CPU heavy, so is kept off the runtime's worker threads.
+        let (jwt_token, new_expire_time) = tokio::task::spawn_blocking(move || {
+            generate_jwt(
+                service_account.private_key,
+                &service_account.client_email,
+                current_time,
+            )
+        })
+        .await??;
 
         let mut state = state.write().await;
 
@@ -184,12 +285,54 @@ async fn refresh_jwt(state: &RwLock<State>) -> Result<String> {
     }
 }
 
+/// The gCloud voice tiers which can be used, from the comma separated `ALLOWED_GCLOUD_TIERS`,
+/// or `None` if every tier is allowed.
+fn get_allowed_tiers() -> Option<&'static [String]> {
+    static ALLOWED_TIERS: OnceLock<Option<Vec<String>>> = OnceLock::new();
+    ALLOWED_TIERS
+        .get_or_init(|| {
+            let tiers = std::env::var("ALLOWED_GCLOUD_TIERS").ok()?;
+            Some(
+                tiers
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect(),
+            )
+        })
+        .as_deref()
+}
+
+/// Allowed tiers match any tier starting with them, so `Chirp` also allows `Chirp3`.
+fn is_tier_allowed(tier: &str) -> bool {
+    get_allowed_tiers().is_none_or(|allowed| {
+        allowed.iter().any(|allowed| {
+            tier.get(..allowed.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(allowed))
+        })
+    })
+}
+
+pub const FORMATS: &[&str] = &["LINEAR16", "OGG_OPUS", "MULAW", "ALAW", "MP3"];
+
+pub fn check_format(format: &str) -> bool {
+    AudioEncoding::from_str(&format.to_uppercase()).is_some()
+}
+
+/// Google rejects requests with more than 5000 bytes of input, including SSML tags.
+const MAX_CHUNK_BYTES: usize = 5000 - "<speak></speak>".len();
+
+#[expect(clippy::too_many_arguments)]
 pub async fn get_tts(
     state: &RwLock<State>,
     text: &str,
     lang: &str,
     speaking_rate: f32,
     preferred_format: Option<&str>,
+    digits_mode: bool,
+    custom_pronunciations: &[CustomPronunciation],
+    voice_clone_key: Option<&str>,
 ) -> Result<(bytes::Bytes, Option<reqwest::header::HeaderValue>)> {
     let jwt_token = refresh_jwt(state).await?;
     let reqwest = state.read().await.reqwest.clone();
@@ -198,37 +341,124 @@ pub async fn get_tts(
         .and_then(|pf| AudioEncoding::from_str(&pf.to_uppercase()))
         .unwrap_or(AudioEncoding::OGG_OPUS);
 
-    let resp = reqwest
-        .post(format!("{GOOGLE_API_BASE}v1/text:synthesize"))
-        .json(&generate_google_json(
-            text,
-            lang,
-            speaking_rate,
-            audio_encoding.as_str(),
-        )?)
-        .header(
-            reqwest::header::AUTHORIZATION,
-            format!("Bearer {jwt_token}"),
-        )
-        .send()
-        .await?
-        .error_for_status()?;
+    let chunks = text::split_chunks(text, Some(lang), MAX_CHUNK_BYTES, |s| {
+        if digits_mode {
+            text::ssml_digits(&text::escape_xml(s)).len()
+        } else {
+            s.len()
+        }
+    });
 
-    let resp_raw = resp.bytes().await?;
-    let audio_response: AudioResponse = serde_json::from_slice(&resp_raw)?;
+    // Chained Ogg streams are valid, but poorly supported by players, so when ffmpeg
+    // is available long text is requested as WAV and encoded to Opus once stitched together.
+    let stitch_opus = chunks.len() > 1
+        && matches!(audio_encoding, AudioEncoding::OGG_OPUS)
+        && ffmpeg::is_enabled();
+
+    let request_encoding = if stitch_opus {
+        AudioEncoding::LINEAR16
+    } else {
+        audio_encoding
+    };
+
+    // Voice clones are only available from the beta API.
+    let api_version = if voice_clone_key.is_some() {
+        "v1beta1"
+    } else {
+        "v1"
+    };
+
+    let mut audio = Vec::new();
+    for chunk in chunks {
+        let resp = reqwest
+            .post(format!("{}{api_version}/text:synthesize", get_api_base()))
+            .json(&generate_google_json(
+                chunk,
+                lang,
+                speaking_rate,
+                request_encoding.as_str(),
+                digits_mode,
+                custom_pronunciations,
+                voice_clone_key,
+            ))
+            .header(
+                reqwest::header::AUTHORIZATION,
+                format!("Bearer {jwt_token}"),
+            )
+            .send()
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(check_quota)?;
+
+        let resp_raw = resp.bytes().await?;
+        let audio_response: AudioResponse = serde_json::from_slice(&resp_raw)?;
+        let chunk_audio =
+            base64::engine::general_purpose::STANDARD.decode(audio_response.audio_content)?;
+
+        if !request_encoding.is_wav() {
+            audio.extend(chunk_audio);
+            continue;
+        }
+
+        // `MULAW` and `ALAW` headers are longer than `LINEAR16`'s, with a `fact` chunk.
+        let Some(chunk_wav) = wav::parse(&chunk_audio) else {
+            anyhow::bail!("gCloud returned malformed WAV audio");
+        };
+
+        // Only the first chunk's header is kept, and fixed up once all chunks are joined.
+        let data_range = chunk_wav.data_range();
+        if audio.is_empty() {
+            audio.extend_from_slice(&chunk_audio[..data_range.end]);
+        } else {
+            audio.extend_from_slice(&chunk_audio[data_range]);
+        }
+    }
+
+    if request_encoding.is_wav() {
+        wav::fix_header(&mut audio)?;
+    }
+
+    if stitch_opus {
+        audio = ffmpeg::run(&audio, &[], ffmpeg::Format::OggOpus)
+            .await?
+            .into();
+    } else if matches!(audio_encoding, AudioEncoding::OGG_OPUS) {
+        audio = check_opus(audio).await?;
+    }
 
     Ok((
-        bytes::Bytes::from(
-            base64::engine::general_purpose::STANDARD.decode(audio_response.audio_content)?,
-        ),
+        bytes::Bytes::from(audio),
         Some(reqwest::header::HeaderValue::from_static(
             audio_encoding.content_type(),
         )),
     ))
 }
 
-static VOICES: tokio::sync::OnceCell<Vec<GoogleVoice>> = tokio::sync::OnceCell::const_new();
-async fn _get_voices(state: &RwLock<State>) -> Result<Vec<GoogleVoice>> {
+/// Checks the Opus audio's Ogg pages, re-encoding it with ffmpeg if they are not a single clean stream.
+async fn check_opus(audio: Vec<u8>) -> Result<Vec<u8>> {
+    let validation = ogg::validate(&audio);
+    let problem = match validation {
+        ogg::Validation::Valid => return Ok(audio),
+        ogg::Validation::Chained => "chained streams",
+        ogg::Validation::Malformed(reason) => reason,
+    };
+
+    if ffmpeg::is_enabled() {
+        tracing::warn!("gCloud returned Opus audio with {problem}, re-encoding");
+        let reencoded = ffmpeg::run(&audio, &[], ffmpeg::Format::OggOpus).await?;
+        return Ok(reencoded.into());
+    }
+
+    // Only expected when multiple chunks are joined without ffmpeg, and is still playable.
+    if matches!(validation, ogg::Validation::Chained) {
+        Ok(audio)
+    } else {
+        anyhow::bail!("gCloud returned malformed Opus audio: {problem}")
+    }
+}
+
+static VOICES: VoiceCache<Vec<GoogleVoice>> = VoiceCache::new();
+async fn fetch_voices(state: &RwLock<State>) -> Result<Vec<GoogleVoice>> {
     #[derive(serde::Deserialize)]
     struct VoiceResponse {
         voices: Vec<GoogleVoice>,
@@ -238,43 +468,70 @@ async fn _get_voices(state: &RwLock<State>) -> Result<Vec<GoogleVoice>> {
     let reqwest = state.read().await.reqwest.clone();
 
     let resp: VoiceResponse = reqwest
-        .get(format!("{GOOGLE_API_BASE}v1/voices"))
+        .get(format!("{}v1/voices", get_api_base()))
         .header("Authorization", format!("Bearer {jwt_token}"))
         .send()
-        .await?
-        .error_for_status()?
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(check_quota)?
         .json()
         .await?;
 
-    Ok(resp.voices)
+    Ok(resp
+        .voices
+        .into_iter()
+        .filter_map(|mut voice| {
+            voice.tier = String::from(parse_tier(&voice.name));
+            is_tier_allowed(&voice.tier).then_some(voice)
+        })
+        .collect())
 }
 
 pub async fn check_voice(state: &RwLock<State>, voice: &str) -> Result<bool> {
     Ok(get_voices(state).await?.iter().any(|s| s.as_str() == voice))
 }
 
-pub async fn get_raw_voices(state: &RwLock<State>) -> Result<&'static Vec<GoogleVoice>> {
-    VOICES.get_or_try_init(|| _get_voices(state)).await
+pub async fn get_raw_voices(state: &RwLock<State>) -> Result<Arc<Vec<GoogleVoice>>> {
+    VOICES.get(|| fetch_voices(state)).await
+}
+
+pub async fn refresh_voices(state: &RwLock<State>) -> Result<()> {
+    VOICES.refresh(|| fetch_voices(state)).await
+}
+
+pub async fn get_languages(state: &RwLock<State>) -> Result<Vec<String>> {
+    let voices = get_raw_voices(state).await?;
+    let languages: BTreeSet<&str> = voices
+        .iter()
+        .map(|gvoice| gvoice.languageCodes[0].as_str())
+        .collect();
+
+    Ok(languages.into_iter().map(String::from).collect())
+}
+
+/// If any voice speaks `language`, such as `en-US` or `cmn-Hant-TW`.
+pub async fn check_language(state: &RwLock<State>, language: &str) -> Result<bool> {
+    let voices = get_raw_voices(state).await?;
+    Ok(voices
+        .iter()
+        .any(|gvoice| gvoice.languageCodes[0] == language))
 }
 
 pub async fn get_voices(state: &RwLock<State>) -> Result<Vec<String>> {
-    Ok(VOICES
-        .get_or_try_init(|| _get_voices(state))
+    Ok(get_raw_voices(state)
         .await?
         .iter()
-        .filter_map(|gvoice| {
-            gvoice
-                .name
-                .splitn(3, '-')
-                .nth(2)?
-                .split_once('-')
-                .filter(|(mode, _)| *mode == "Standard")
-                .map(|(_, variant)| {
+        .map(|gvoice| {
+            // Standard voices keep the original `{lang} {variant}` naming.
+            match gvoice.name.rsplit_once('-') {
+                Some((_, variant)) if gvoice.tier == "Standard" => {
                     let [mut language] = gvoice.languageCodes.clone();
                     language.push(' ');
                     language.push_str(variant);
                     language
-                })
+                }
+                _ => gvoice.name.clone(),
+            }
         })
         .collect())
 }
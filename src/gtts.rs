@@ -1,23 +1,34 @@
 use std::{
-    sync::{atomic::AtomicBool, Arc, OnceLock},
+    collections::BTreeMap,
+    sync::{atomic::AtomicBool, Arc, LazyLock, OnceLock},
     time::Duration,
 };
 
 use aformat::ToArrayString;
+use arc_swap::ArcSwap;
+use base64::Engine as _;
 use ipgen::IpNetwork;
-use itertools::Itertools;
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 use tokio::sync::RwLock;
 
-use crate::{DeadlineMonitor, Result};
+use crate::{parse_env, text, DeadlineMonitor, RateLimited, Result, TextTooLong};
 
 #[derive(Clone)]
 pub struct State {
     ip: std::net::IpAddr,
     ip_block: Option<IpNetwork>,
+    extra_headers: reqwest::header::HeaderMap,
+    /// Picks the IPs to rotate to, kept so a seeded RNG stays deterministic across rotations.
+    rng: rand::rngs::StdRng,
     pub http: reqwest::Client,
 }
 
+impl State {
+    pub fn ip_block(&self) -> Option<IpNetwork> {
+        self.ip_block
+    }
+}
+
 fn get_base_url() -> reqwest::Url {
     static BASE_URL: OnceLock<reqwest::Url> = OnceLock::new();
     BASE_URL
@@ -40,28 +51,53 @@ fn parse_url(text: &str, lang: &str) -> reqwest::Url {
     url
 }
 
-pub async fn get_random_ipv6(ip_block: Option<IpNetwork>) -> Result<State> {
+/// Generates an IP in the block from a random name, deterministic for a seeded `rng`.
+fn generate_ip(ip_block: IpNetwork, rng: &mut impl Rng) -> std::net::IpAddr {
+    let name: String = rng
+        .sample_iter::<char, _>(rand::distributions::Standard)
+        .take(16)
+        .collect();
+
+    tracing::debug!("Generated random name: {:?}", name.as_bytes());
+    ipgen::ip(&name, ip_block).unwrap()
+}
+
+pub async fn get_random_ipv6(
+    ip_block: Option<IpNetwork>,
+    extra_headers: reqwest::header::HeaderMap,
+) -> Result<State> {
+    get_ipv6_with_rng(ip_block, extra_headers, rand::rngs::StdRng::from_entropy()).await
+}
+
+/// Finds an unblocked IP in the block, with IPs picked by `rng` so the rotation
+/// can be made deterministic by passing a seeded RNG.
+///
+/// The RNG is stored in the returned [`State`] and reused when the IP is blocked.
+pub async fn get_ipv6_with_rng(
+    ip_block: Option<IpNetwork>,
+    extra_headers: reqwest::header::HeaderMap,
+    mut rng: rand::rngs::StdRng,
+) -> Result<State> {
     let Some(ip_block) = ip_block else {
         return Ok(State {
+            rng,
             ip_block: None,
             ip: "0.0.0.0".parse()?,
-            http: reqwest::Client::new(),
+            http: reqwest::Client::builder()
+                .default_headers(extra_headers.clone())
+                .build()?,
+            extra_headers,
         });
     };
 
     let mut attempts = 1;
     loop {
-        let name: String = rand::thread_rng()
-            .sample_iter::<char, _>(rand::distributions::Standard)
-            .take(16)
-            .collect();
-
-        tracing::debug!("Generated random name: {:?}", name.as_bytes());
-        let ip = ipgen::ip(&name, ip_block).unwrap();
+        let ip = generate_ip(ip_block, &mut rng);
 
         let http = reqwest::Client::builder()
             .connect_timeout(std::time::Duration::from_secs(5))
             .local_address(Some(ip))
+            .default_headers(extra_headers.clone())
             .build()?;
 
         let check_request = http.get(parse_url("Hello", "en")).send().await;
@@ -70,12 +106,15 @@ pub async fn get_random_ipv6(ip_block: Option<IpNetwork>) -> Result<State> {
                 tracing::warn!("Generated random IP: {ip}");
                 break Ok(State {
                     ip,
+                    rng,
                     http,
+                    extra_headers,
                     ip_block: Some(ip_block),
                 });
             }
             CheckResult::NormalBlock => "429 block",
             CheckResult::TimeoutBlock => "timeout block",
+            CheckResult::NonAudioResponse => "non-audio response",
             CheckResult::HostUnreachable => "unreachable error",
         };
 
@@ -89,6 +128,8 @@ enum CheckResult {
     NormalBlock,
     TimeoutBlock,
     HostUnreachable,
+    /// Google sometimes responds with an HTML consent or CAPTCHA page instead of audio.
+    NonAudioResponse,
 }
 
 fn is_host_unreachable(err: &reqwest::Error) -> bool {
@@ -98,16 +139,117 @@ fn is_host_unreachable(err: &reqwest::Error) -> bool {
         .all(|s| debug_message.contains(s))
 }
 
+/// The content types accepted as audio from Google, from the comma separated
+/// `GTTS_CONTENT_TYPES` or just `audio/mpeg` if unset.
+fn get_trusted_content_types() -> &'static [String] {
+    static CONTENT_TYPES: OnceLock<Vec<String>> = OnceLock::new();
+    CONTENT_TYPES.get_or_init(|| match std::env::var("GTTS_CONTENT_TYPES") {
+        Ok(content_types) => content_types
+            .split(',')
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::to_ascii_lowercase)
+            .collect(),
+        Err(_) => vec![String::from("audio/mpeg")],
+    })
+}
+
+fn is_audio(content_type: Option<&reqwest::header::HeaderValue>, body: &[u8]) -> bool {
+    if let Some(content_type) = content_type {
+        let Ok(content_type) = content_type.to_str() else {
+            return false;
+        };
+
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        if !get_trusted_content_types()
+            .iter()
+            .any(|trusted| trusted.eq_ignore_ascii_case(essence))
+        {
+            tracing::debug!("Rejecting gTTS response with content type {content_type}");
+            return false;
+        }
+    }
+
+    let body_start = body.trim_ascii_start();
+    !(body_start.starts_with(b"<") || body_start.starts_with(b"{"))
+}
+
+/// How gTTS responses are decoded, from `GTTS_RESPONSE_FORMAT`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ResponseFormat {
+    /// The body is the audio itself.
+    Raw,
+    /// JSON responses hold the audio as base64, as returned by some alternate endpoints and proxies.
+    Json,
+}
+
+fn get_response_format() -> ResponseFormat {
+    static RESPONSE_FORMAT: OnceLock<ResponseFormat> = OnceLock::new();
+    *RESPONSE_FORMAT.get_or_init(|| match std::env::var("GTTS_RESPONSE_FORMAT").as_deref() {
+        Ok("json") => ResponseFormat::Json,
+        Ok("raw") | Err(_) => ResponseFormat::Raw,
+        Ok(other) => {
+            tracing::warn!("Unknown GTTS_RESPONSE_FORMAT {other}, using raw");
+            ResponseFormat::Raw
+        }
+    })
+}
+
+fn is_json(content_type: Option<&reqwest::header::HeaderValue>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("application/json"))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum JsonAudio {
+    Encoded(String),
+    Wrapped {
+        #[serde(rename = "audioContent")]
+        audio_content: String,
+    },
+}
+
+/// Decodes audio sent as a base64 JSON string, or in the `audioContent` field of a JSON object.
+fn decode_json_audio(body: &[u8]) -> Option<bytes::Bytes> {
+    let (JsonAudio::Encoded(encoded)
+    | JsonAudio::Wrapped {
+        audio_content: encoded,
+    }) = serde_json::from_slice(body).ok()?;
+
+    let audio = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()?;
+
+    Some(audio.into())
+}
+
 async fn is_block(resp: reqwest::Result<reqwest::Response>) -> Result<CheckResult> {
     match resp {
         Ok(mut resp) => {
             if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
                 Ok(CheckResult::NormalBlock)
             } else {
-                let content_type = resp.headers_mut().remove(reqwest::header::CONTENT_TYPE);
-                let audio = resp.error_for_status()?.bytes().await?;
+                let mut content_type = resp.headers_mut().remove(reqwest::header::CONTENT_TYPE);
+                let mut audio = resp.error_for_status()?.bytes().await?;
+
+                if get_response_format() == ResponseFormat::Json && is_json(content_type.as_ref()) {
+                    let Some(decoded) = decode_json_audio(&audio) else {
+                        tracing::debug!("Rejecting gTTS JSON response without base64 audio");
+                        return Ok(CheckResult::NonAudioResponse);
+                    };
+
+                    content_type = Some(reqwest::header::HeaderValue::from_static("audio/mpeg"));
+                    audio = decoded;
+                }
 
-                Ok(CheckResult::Ok(content_type, audio))
+                if is_audio(content_type.as_ref(), &audio) {
+                    Ok(CheckResult::Ok(content_type, audio))
+                } else {
+                    Ok(CheckResult::NonAudioResponse)
+                }
             }
         }
         Err(err) => {
@@ -122,60 +264,246 @@ async fn is_block(resp: reqwest::Result<reqwest::Response>) -> Result<CheckResul
     }
 }
 
+const CHUNK_LENGTH: usize = 200;
+
+#[derive(Clone, Copy)]
+struct ChunkLimit {
+    max_chunks: Option<usize>,
+    truncate: bool,
+}
+
+fn get_chunk_limit() -> &'static ChunkLimit {
+    static CHUNK_LIMIT: OnceLock<ChunkLimit> = OnceLock::new();
+    CHUNK_LIMIT.get_or_init(|| ChunkLimit {
+        max_chunks: parse_env("GTTS_MAX_CHUNKS"),
+        truncate: parse_env("GTTS_TRUNCATE_CHUNKS").unwrap_or(false),
+    })
+}
+
+/// Chunk audio shorter than this per character of speech is assumed to be missing a section.
+const MIN_BYTES_PER_CHARACTER: usize = 16;
+const MAX_SHORT_CHUNK_RETRIES: u8 = 3;
+/// How many times a request can be blocked, each time rotating to a new IP, before giving up.
+const MAX_BLOCK_RETRIES: u8 = 5;
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(2);
+
 pub async fn get_tts(
     state: &RwLock<State>,
     text: &str,
     voice: &str,
     hit_any_deadline: Arc<AtomicBool>,
 ) -> Result<(bytes::Bytes, Option<reqwest::header::HeaderValue>)> {
-    let _guard = DeadlineMonitor::new(Duration::from_millis(3000), hit_any_deadline, |took| {
+    let _guard = DeadlineMonitor::new(Duration::from_secs(3), hit_any_deadline, |took| {
         tracing::warn!("Fetching gTTS audio took {} millis!", took.as_millis());
     });
 
+    // Bare language codes are pinned to a region, so the accent doesn't depend on the host.
+    let regions = REGIONS.load_full();
+    let voice = regions.get(voice).map_or(voice, String::as_str);
+
     let mut content_type = None;
     let mut audio = Vec::new();
+    let mut block_retries = 0;
+
+    let mut chunks = text::split_chunks(text, Some(voice), CHUNK_LENGTH, |s| s.chars().count());
+
+    // Each chunk is a separate request to Google, so bound the fan-out of long messages.
+    let ChunkLimit {
+        max_chunks,
+        truncate,
+    } = *get_chunk_limit();
+    if let Some(max_chunks) = max_chunks {
+        if chunks.len() > max_chunks {
+            if !truncate {
+                return Err(TextTooLong(max_chunks * CHUNK_LENGTH).into());
+            }
+
+            tracing::debug!("Truncating {} gTTS chunks to {max_chunks}", chunks.len());
+            chunks.truncate(max_chunks);
+        }
+    }
 
-    let chunks: Vec<String> = text
-        .chars()
-        .chunks(200)
-        .into_iter()
-        .map(Iterator::collect)
-        .collect();
     for chunk in chunks {
+        let speech_chars = chunk.chars().filter(|c| c.is_alphanumeric()).count();
+        let mut short_chunk_retries = 0;
+        let mut retry_delay = INITIAL_RETRY_DELAY;
         loop {
             let (ip, result) = {
                 let State { ip, http, .. } = state.read().await.clone();
-                (ip, http.get(parse_url(&chunk, voice)).send().await)
+                (ip, http.get(parse_url(chunk, voice)).send().await)
             };
 
-            if let CheckResult::Ok(content_type_, audio_chunk) = is_block(result).await? {
-                if let Some(content_type_) = content_type_ {
-                    content_type = Some(content_type_);
+            let blocked = if let CheckResult::Ok(content_type_, audio_chunk) =
+                is_block(result).await?
+            {
+                if audio_chunk.len() >= speech_chars * MIN_BYTES_PER_CHARACTER {
+                    if let Some(content_type_) = content_type_ {
+                        content_type = Some(content_type_);
+                    }
+
+                    break audio.extend(audio_chunk);
                 }
 
-                break audio.extend(audio_chunk);
-            }
+                // Google sometimes "succeeds" with a near empty clip, which would silently drop this chunk.
+                if short_chunk_retries == MAX_SHORT_CHUNK_RETRIES {
+                    anyhow::bail!(
+                        "gTTS returned only {} bytes of audio for a chunk with {speech_chars} characters",
+                        audio_chunk.len()
+                    );
+                }
+
+                short_chunk_retries += 1;
+                tracing::warn!(
+                    "gTTS returned only {} bytes of audio for a chunk with {speech_chars} characters, retrying",
+                    audio_chunk.len()
+                );
+                false
+            } else {
+                true
+            };
 
             // Generate a new client, with an new IP, and try again
-            let mut state = state.write().await;
-            if state.ip == ip {
-                tracing::warn!("IP {ip} has been blocked!");
-                *state = get_random_ipv6(state.ip_block).await?;
+            if blocked {
+                let mut state = state.write().await;
+                if state.ip_block.is_none() {
+                    tracing::warn!(
+                        "gTTS blocked a request, and IPV6_BLOCK is not set to rotate IPs"
+                    );
+                    return Err(RateLimited.into());
+                }
+
+                if block_retries == MAX_BLOCK_RETRIES {
+                    tracing::warn!("gTTS blocked a request {MAX_BLOCK_RETRIES} times, giving up");
+                    return Err(RateLimited.into());
+                }
+
+                block_retries += 1;
+                if state.ip == ip {
+                    tracing::warn!("IP {ip} has been blocked!");
+                    *state = get_ipv6_with_rng(
+                        state.ip_block,
+                        state.extra_headers.clone(),
+                        state.rng.clone(),
+                    )
+                    .await?;
+                }
             }
+
+            // Space out retries, to avoid making the block worse by hammering Google.
+            tokio::time::sleep(retry_delay).await;
+            retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
         }
     }
 
     Ok((bytes::Bytes::from(audio), content_type))
 }
 
+type VoiceMap = BTreeMap<String, String>;
+
+static VOICES: LazyLock<ArcSwap<VoiceMap>> = LazyLock::new(ArcSwap::default);
+static REGIONS: LazyLock<ArcSwap<VoiceMap>> = LazyLock::new(ArcSwap::default);
+
+fn read_voices() -> Result<VoiceMap> {
+    let voices = match std::env::var("GTTS_VOICES_PATH") {
+        Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        Err(_) => serde_json::from_str(include_str!("data/voices-gtts.json"))?,
+    };
+
+    Ok(voices)
+}
+
+fn read_regions() -> Result<VoiceMap> {
+    let regions = match std::env::var("GTTS_REGIONS_PATH") {
+        Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        Err(_) => serde_json::from_str(include_str!("data/regions-gtts.json"))?,
+    };
+
+    Ok(regions)
+}
+
+/// (Re)loads the voice list from `GTTS_VOICES_PATH` and the regional defaults from
+/// `GTTS_REGIONS_PATH`, or the bundled copies if unset.
+///
+/// This does blocking IO, so should be called at startup or via `spawn_blocking`.
+pub fn load_voices() -> Result<()> {
+    let voices = read_voices()?;
+    let mut regions = read_regions()?;
+    regions.retain(|language, region| {
+        let exists = voices.contains_key(region);
+        if !exists {
+            tracing::warn!("Ignoring gTTS region {region} for {language}, as it is not a voice");
+        }
+
+        exists
+    });
+
+    tracing::info!(
+        "Loaded {} gTTS voices and {} regional defaults",
+        voices.len(),
+        regions.len()
+    );
+
+    VOICES.store(Arc::new(voices));
+    REGIONS.store(Arc::new(regions));
+    Ok(())
+}
+
+/// Lets Google detect the language of the text, so is always valid despite not being listed.
+pub const AUTO_VOICE: &str = "auto";
+
 pub fn check_voice(voice: &str) -> bool {
-    get_voices().iter().any(|s| s.as_str() == voice)
+    voice == AUTO_VOICE || VOICES.load().contains_key(voice)
 }
 
 pub fn get_voices() -> Vec<String> {
-    get_raw_voices().into_keys().collect()
+    VOICES.load().keys().cloned().collect()
+}
+
+pub fn get_languages() -> Vec<String> {
+    get_voices()
 }
 
-pub fn get_raw_voices() -> std::collections::BTreeMap<String, String> {
-    serde_json::from_str(include_str!("data/voices-gtts.json")).unwrap()
+pub fn get_raw_voices() -> Arc<VoiceMap> {
+    VOICES.load_full()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::generate_ip;
+
+    #[test]
+    fn seeded_ips_are_deterministic_and_in_block() {
+        let ip_block = "2001:db8:1234::/48".parse().unwrap();
+        let generate = |seed| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            (0..16)
+                .map(|_| generate_ip(ip_block, &mut rng))
+                .collect::<Vec<_>>()
+        };
+
+        let ips = generate(42);
+        assert_eq!(ips, generate(42));
+        assert_ne!(ips, generate(43));
+
+        for ip in &ips {
+            let std::net::IpAddr::V6(ip) = ip else {
+                panic!("{ip} is not an IPv6 address");
+            };
+
+            assert_eq!(
+                ip.segments()[..3],
+                [0x2001, 0xdb8, 0x1234],
+                "{ip} is outside the block"
+            );
+        }
+
+        let mut deduped = ips.clone();
+        deduped.sort_unstable();
+        deduped.dedup();
+        assert_eq!(deduped.len(), ips.len(), "seeded IPs should not repeat");
+    }
 }
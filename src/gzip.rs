@@ -0,0 +1,32 @@
+use std::io::{Read, Write};
+
+use crate::Result;
+
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// If the `Accept-Encoding` header allows gzip, ignoring it if given a quality of 0.
+pub fn is_accepted(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get_all(axum::http::header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|encoding| {
+            let mut params = encoding.split(';').map(str::trim);
+            let is_gzip = params
+                .next()
+                .is_some_and(|name| name.eq_ignore_ascii_case("gzip") || name == "*");
+
+            is_gzip && !params.any(|param| matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"))
+        })
+}
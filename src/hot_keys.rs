@@ -0,0 +1,53 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use mini_moka::sync::Cache;
+
+use crate::AudioCacheDigest;
+
+/// Counts how often each cache key is requested, only keeping the counts of the
+/// most recently requested keys so memory stays bounded.
+pub struct HotKeys {
+    counts: Cache<AudioCacheDigest, Arc<AtomicU64>>,
+}
+
+#[derive(serde::Serialize)]
+pub struct HotKey {
+    key_hash: String,
+    count: u64,
+}
+
+impl HotKeys {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            counts: Cache::new(capacity),
+        }
+    }
+
+    pub fn record(&self, key_hash: &AudioCacheDigest) {
+        match self.counts.get(key_hash) {
+            Some(count) => {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+            None => self.counts.insert(*key_hash, Arc::new(AtomicU64::new(1))),
+        }
+    }
+
+    /// Returns the `limit` most requested keys, most requested first.
+    pub fn top(&self, limit: usize) -> Vec<HotKey> {
+        let mut hot_keys: Vec<_> = self
+            .counts
+            .iter()
+            .map(|entry| HotKey {
+                key_hash: format!("{:x}", entry.key()),
+                count: entry.value().load(Ordering::Relaxed),
+            })
+            .collect();
+
+        hot_keys.sort_unstable_by_key(|hot_key| std::cmp::Reverse(hot_key.count));
+        hot_keys.truncate(limit);
+        hot_keys
+    }
+}
@@ -0,0 +1,50 @@
+//! Detects the language of text, so text not in the voice's language can be rejected, see `strict_language`.
+
+use crate::{translation, Result, State};
+
+/// The service used to detect languages, more can be added without changing `/tts`.
+pub enum Detector {
+    /// Detects the language as part of translating the text, which uses `DeepL` quota.
+    DeepL,
+}
+
+impl Detector {
+    pub fn from_env() -> Option<Self> {
+        translation::Keys::is_configured().then_some(Self::DeepL)
+    }
+
+    /// Returns the lowercase primary language subtag of the text, such as `en`, if it could be detected.
+    pub async fn detect(&self, state: &State, text: &str) -> Result<Option<String>> {
+        match self {
+            Self::DeepL => {
+                let Some(keys) = &state.translation_keys else {
+                    return Ok(None);
+                };
+
+                let translation =
+                    translation::translate(&state.reqwest, keys, text, "EN-US").await?;
+                Ok(translation
+                    .map(|translation| primary_subtag(&translation.detected_source_language)))
+            }
+        }
+    }
+}
+
+/// The lowercase primary subtag of a language code, such as `en` for `en-GB`.
+pub fn primary_subtag(language: &str) -> String {
+    language
+        .split(['-', '_', ' '])
+        .next()
+        .unwrap_or(language)
+        .to_lowercase()
+}
+
+/// The language of an mbrola voice, which are named after the language with a number.
+pub fn espeak_voice_language(voice: &str) -> String {
+    match voice.trim_end_matches(|c: char| c.is_ascii_digit()) {
+        // A few voices are named after the country instead.
+        "us" => String::from("en"),
+        "br" => String::from("pt"),
+        language => language.to_owned(),
+    }
+}
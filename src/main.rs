@@ -8,7 +8,9 @@
 )]
 
 use std::{
-    fmt::Display,
+    borrow::Cow,
+    collections::HashMap,
+    fmt::{Display, Write as _},
     str::FromStr,
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
@@ -17,15 +19,19 @@ use std::{
     time::{Duration, Instant},
 };
 
+use aformat::ToArrayString;
 use arc_swap::ArcSwap;
 use axum::{
+    extract::DefaultBodyLimit,
     http::header::HeaderValue,
     response::Response,
     routing::{get, post},
     Json,
 };
+use base64::Engine as _;
 use bytes::Bytes;
 use mini_moka::sync::Cache;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use reqwest::StatusCode;
 use serde_json::to_value;
 use sha2::{
@@ -36,11 +42,29 @@ use small_fixed_array::{FixedString, ValidLength};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod chaos;
+mod elevenlabs;
 mod espeak;
+mod ffmpeg;
 mod gcloud;
 mod gtts;
+mod gzip;
+mod hot_keys;
+mod language;
+mod ogg;
 mod polly;
+mod postprocess;
+mod range;
+mod sentence_cache;
+mod sentences;
+mod server;
+mod sniff;
+mod subprocess;
+mod text;
 mod translation;
+mod voice_aliases;
+mod voice_cache;
+mod wav;
 
 type Result<T, E = anyhow::Error> = std::result::Result<T, E>;
 type ResponseResult<T> = std::result::Result<T, Error>;
@@ -52,6 +76,9 @@ pub fn check_mp3_length(audio: &[u8], max_length: u64) -> bool {
     mp3_duration::from_read(&mut audio.reader()).map_or(true, |d| d.as_secs() < max_length)
 }
 
+/// Audio shorter than this cannot contain any speech, such as eSpeak's header-only output.
+const MIN_AUDIO_BYTES: usize = 256;
+
 pub struct DeadlineMonitor<F: FnOnce(Duration)> {
     start: Instant,
     expected: Duration,
@@ -88,43 +115,243 @@ struct GetVoices {
     mode: TTSMode,
     #[serde(default)]
     raw: bool,
+    #[serde(default)]
+    aliases: bool,
+}
+
+#[derive(serde::Serialize)]
+struct VoiceName<'a> {
+    name: &'a str,
+    alias_of: Option<&'a str>,
 }
 
 async fn get_voices(
     axum::extract::Query(payload): axum::extract::Query<GetVoices>,
 ) -> ResponseResult<impl axum::response::IntoResponse> {
-    let GetVoices { mode, raw } = payload;
+    let GetVoices { mode, raw, aliases } = payload;
     let state = STATE.get().unwrap();
 
+    if aliases && !raw {
+        let voices = mode.voices(state).await?;
+        let voice_names: Vec<_> = voices
+            .iter()
+            .map(|voice| VoiceName {
+                name: voice,
+                alias_of: None,
+            })
+            .chain(
+                voice_aliases::for_mode(&state.voice_aliases, mode).map(|(name, alias)| {
+                    VoiceName {
+                        name,
+                        alias_of: Some(alias.voice.as_str()),
+                    }
+                }),
+            )
+            .collect();
+
+        return Ok(axum::Json(to_value(voice_names)?));
+    }
+
     Ok(axum::Json(if raw {
         match mode {
-            TTSMode::gTTS => to_value(gtts::get_raw_voices()),
+            TTSMode::gTTS => to_value(&*gtts::get_raw_voices()),
             TTSMode::eSpeak => to_value(espeak::get_voices()),
-            TTSMode::Polly => to_value(polly::get_raw_voices(&state.polly).await?),
-            TTSMode::gCloud => to_value(gcloud::get_raw_voices(&state.gcloud).await?),
+            TTSMode::Polly => to_value(&*polly::get_raw_voices(&state.polly).await?),
+            TTSMode::gCloud => to_value(&*gcloud::get_raw_voices(&state.gcloud).await?),
+            TTSMode::ElevenLabs => to_value(&*elevenlabs::get_raw_voices(&state.elevenlabs).await?),
         }?
     } else {
-        to_value(match mode {
-            TTSMode::gTTS => gtts::get_voices(),
-            TTSMode::eSpeak => espeak::get_voices().to_vec(),
-            TTSMode::Polly => polly::get_voices(&state.polly).await?,
-            TTSMode::gCloud => gcloud::get_voices(&state.gcloud).await?,
-        })?
+        to_value(mode.voices(state).await?)?
+    }))
+}
+
+async fn reload_voices(headers: axum::http::HeaderMap) -> ResponseResult<StatusCode> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    tokio::task::spawn_blocking(gtts::load_voices).await??;
+    Ok(StatusCode::OK)
+}
+
+#[derive(serde::Deserialize)]
+struct GetLanguages {
+    mode: TTSMode,
+}
+
+async fn refresh_voices(
+    headers: axum::http::HeaderMap,
+    axum::extract::Query(GetLanguages { mode }): axum::extract::Query<GetLanguages>,
+) -> ResponseResult<StatusCode> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    match mode {
+        TTSMode::gTTS => tokio::task::spawn_blocking(gtts::load_voices).await??,
+        // eSpeak voices are read from disk once at startup, so there is nothing to refresh.
+        TTSMode::eSpeak => {}
+        TTSMode::Polly => polly::refresh_voices(&state.polly).await?,
+        TTSMode::gCloud => gcloud::refresh_voices(&state.gcloud).await?,
+        TTSMode::ElevenLabs => elevenlabs::refresh_voices(&state.elevenlabs).await?,
+    }
+
+    Ok(StatusCode::OK)
+}
+
+async fn get_languages(
+    axum::extract::Query(GetLanguages { mode }): axum::extract::Query<GetLanguages>,
+) -> ResponseResult<Json<Vec<String>>> {
+    let state = STATE.get().unwrap();
+
+    Ok(Json(match mode {
+        TTSMode::gTTS => gtts::get_languages(),
+        TTSMode::eSpeak => espeak::get_languages(),
+        TTSMode::Polly => polly::get_languages(&state.polly).await?,
+        TTSMode::gCloud => gcloud::get_languages(&state.gcloud).await?,
+        TTSMode::ElevenLabs => elevenlabs::get_languages(&state.elevenlabs).await?,
     }))
 }
 
 async fn get_translation_languages() -> ResponseResult<Json<Vec<(FixedString, FixedString)>>> {
     let state = STATE.get().unwrap();
-    let Some(token) = &state.translation_key else {
+    let Some(keys) = &state.translation_keys else {
         return Ok(Json(Vec::new()));
     };
 
-    match translation::get_languages(&state.reqwest, token).await {
+    match translation::get_languages(&state.reqwest, keys).await {
         Ok(languages) => Ok(Json(languages)),
-        Err(err) => Err(Error::Unknown(err)),
+        Err(err) => Err(Error::from(err)),
     }
 }
 
+#[derive(serde::Deserialize)]
+struct Translate {
+    text: FixedString,
+    target_lang: FixedString<u8>,
+}
+
+async fn translate(
+    headers: axum::http::HeaderMap,
+    axum::extract::Query(payload): axum::extract::Query<Translate>,
+) -> ResponseResult<Json<Option<translation::Translation>>> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    let Some(keys) = &state.translation_keys else {
+        return Err(Error::TranslationDisabled);
+    };
+
+    let translation =
+        translation::translate(&state.reqwest, keys, &payload.text, &payload.target_lang).await?;
+
+    Ok(Json(translation))
+}
+
+const SAMPLE_TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+const MAX_BENCHMARK_ITERATIONS: u8 = 10;
+
+#[derive(serde::Deserialize)]
+struct Benchmark {
+    mode: TTSMode,
+    #[serde(rename = "lang")]
+    voice: FixedString<u8>,
+    iterations: Option<u8>,
+}
+
+#[derive(serde::Serialize)]
+struct BenchmarkResult {
+    iterations: u8,
+    min_ms: f64,
+    max_ms: f64,
+    mean_ms: f64,
+    p95_ms: f64,
+}
+
+async fn benchmark(
+    headers: axum::http::HeaderMap,
+    axum::extract::Query(payload): axum::extract::Query<Benchmark>,
+) -> ResponseResult<Json<BenchmarkResult>> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    let Benchmark { mode, voice, .. } = payload;
+    mode.check_voice(state, &voice).await?;
+
+    let iterations = payload
+        .iterations
+        .unwrap_or(5)
+        .clamp(1, MAX_BENCHMARK_ITERATIONS);
+
+    let mut latencies = Vec::with_capacity(iterations.into());
+    for _ in 0..iterations {
+        let start = Instant::now();
+        mode.synthesize(
+            state,
+            FixedString::from_static_trunc(SAMPLE_TEXT),
+            &voice,
+            &SynthesisOptions::default(),
+            Arc::default(),
+        )
+        .await?;
+
+        latencies.push(start.elapsed());
+    }
+
+    latencies.sort_unstable();
+    let to_ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let p95_index = (latencies.len() * 95).div_ceil(100) - 1;
+
+    Ok(Json(BenchmarkResult {
+        iterations,
+        min_ms: to_ms(latencies[0]),
+        max_ms: to_ms(latencies[latencies.len() - 1]),
+        mean_ms: to_ms(latencies.iter().sum::<Duration>() / u32::from(iterations)),
+        p95_ms: to_ms(latencies[p95_index]),
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct GetModes {
+    #[serde(default)]
+    detailed: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ModeInfo {
+    mode: TTSMode,
+    default_voice: Option<FixedString<u8>>,
+    formats: &'static [&'static str],
+    default_format: Option<FixedString<u8>>,
+    min_speaking_rate: Option<f32>,
+    max_speaking_rate: Option<f32>,
+    speaking_rate_unit: Option<&'static str>,
+    healthy: bool,
+}
+
+async fn get_modes(
+    axum::extract::Query(GetModes { detailed }): axum::extract::Query<GetModes>,
+) -> ResponseResult<Json<serde_json::Value>> {
+    let state = STATE.get().unwrap();
+    let modes = TTSMode::configured(state);
+    if !detailed {
+        return Ok(Json(to_value(modes.collect::<Vec<_>>())?));
+    }
+
+    Ok(Json(to_value(
+        modes
+            .map(|mode| ModeInfo {
+                mode,
+                default_voice: state.default_voice.get(mode).clone(),
+                formats: mode.formats(),
+                default_format: state.default_format.get(mode).clone(),
+                min_speaking_rate: mode.min_speaking_rate(),
+                max_speaking_rate: mode.max_speaking_rate(),
+                speaking_rate_unit: mode.speaking_rate_unit(),
+                healthy: state.healthy.get(mode).load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>(),
+    )?))
+}
+
 #[derive(serde::Serialize)]
 struct CacheInfo {
     hits: u64,
@@ -144,6 +371,174 @@ async fn get_cache_info() -> Json<CacheInfo> {
     })
 }
 
+#[derive(serde::Deserialize)]
+struct GetHotKeys {
+    limit: Option<usize>,
+}
+
+async fn get_hot_keys(
+    headers: axum::http::HeaderMap,
+    axum::extract::Query(GetHotKeys { limit }): axum::extract::Query<GetHotKeys>,
+) -> ResponseResult<Json<Vec<hot_keys::HotKey>>> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    let hot_keys = state
+        .hot_keys
+        .as_ref()
+        .map_or_else(Vec::new, |hot_keys| hot_keys.top(limit.unwrap_or(10)));
+
+    Ok(Json(hot_keys))
+}
+
+#[derive(serde::Serialize)]
+struct CachedPrefix {
+    /// The number of characters at the start of the (normalized) text which are already cached.
+    cached_length: usize,
+}
+
+/// Finds the longest prefix of the text, ending at a word boundary, which has been cached
+/// with the same options, so clients sending progressively longer text only synthesize the rest.
+async fn get_cached_prefix(
+    axum::extract::Query(payload): axum::extract::Query<GetTTS>,
+    headers: axum::http::HeaderMap,
+) -> ResponseResult<Json<CachedPrefix>> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    let voice = state.requested_voice(payload.mode, payload.voice)?;
+    let (mode, voice) = voice_aliases::resolve(&state.voice_aliases, payload.mode, voice);
+    mode.check_voice(state, &voice).await?;
+
+    let text = if state.normalize_text {
+        text::normalize(&payload.text)
+    } else {
+        Cow::Borrowed(payload.text.as_str())
+    };
+
+    let translation_lang = payload
+        .translation_lang
+        .filter(|lang| !lang.eq_ignore_ascii_case("none"));
+    let preferred_format = payload
+        .preferred_format
+        .or_else(|| state.default_format.get(mode).clone());
+    let custom_pronunciations = match payload.custom_pronunciations.as_deref() {
+        Some(raw) => mode.check_custom_pronunciations(raw)?,
+        None => Vec::new(),
+    };
+    let style = match payload.style.as_deref() {
+        Some(style) => Some(mode.check_style(state, &voice, style).await?),
+        None => None,
+    };
+
+    let options = SynthesisOptions {
+        speaking_rate: payload.speaking_rate,
+        preferred_format: preferred_format.as_deref(),
+        style,
+        sample_rate: payload.sample_rate_hz,
+        digits_mode: payload.digits_mode,
+        custom_pronunciations: &custom_pronunciations,
+        voice_clone_key: payload.voice_clone_key.as_deref(),
+    };
+
+    let key_suffix = cache_key_suffix(
+        state,
+        mode,
+        &voice,
+        &options,
+        translation_lang.as_deref(),
+        payload.trim_silence && ffmpeg::is_enabled(),
+        payload.target_lufs.filter(|_| ffmpeg::is_enabled()),
+    )?;
+
+    let word_ends = text
+        .char_indices()
+        .filter(|(_, c)| c.is_whitespace())
+        .map(|(index, _)| index)
+        .chain(std::iter::once(text.len()));
+
+    let audio_cache = state.cache.load();
+    let cached_prefix = word_ends
+        .rev()
+        .map(|end| &text[..end])
+        .filter(|prefix| !prefix.trim().is_empty())
+        .find(|prefix| {
+            let mut cache_key = cache_key_text(state, prefix).into_owned();
+            cache_key.push_str(&key_suffix);
+
+            audio_cache.contains_key(&sha2::Sha256::digest(&cache_key))
+        });
+
+    Ok(Json(CachedPrefix {
+        cached_length: cached_prefix.map_or(0, |prefix| prefix.chars().count()),
+    }))
+}
+
+/// The effective configuration of the service, without any secrets.
+#[derive(serde::Serialize)]
+#[expect(clippy::struct_excessive_bools)]
+struct Config {
+    auth_enabled: bool,
+    translation_enabled: bool,
+    translation_key_count: usize,
+    /// The index in `DEEPL_KEYS` of the key most recently used.
+    translation_active_key: Option<usize>,
+    ffmpeg_enabled: bool,
+    postprocess_enabled: bool,
+    normalize_text: bool,
+    normalize_cache_key: bool,
+    gzip_espeak: bool,
+    modes: Vec<TTSMode>,
+    gtts_ip_rotation: bool,
+    cache_max_capacity: Option<u64>,
+    cache_large_item_bytes: Option<usize>,
+    cache_large_max_bytes: Option<u64>,
+    cache_min_text_length: usize,
+    max_text_length: &'static PerMode<Option<usize>>,
+    default_max_length: Option<u64>,
+    max_length_cap: Option<u64>,
+    max_request_timeout_ms: u64,
+    default_format: &'static PerMode<Option<FixedString<u8>>>,
+    max_body_bytes: usize,
+}
+
+async fn get_config(headers: axum::http::HeaderMap) -> ResponseResult<Json<Config>> {
+    let state = STATE.get().unwrap();
+    check_auth(state, &headers)?;
+
+    let gtts_ip_rotation = state.gtts.read().await.ip_block().is_some();
+    let cache = state.cache.load();
+    Ok(Json(Config {
+        auth_enabled: !state.auth_keys.is_empty(),
+        translation_enabled: state.translation_keys.is_some(),
+        translation_key_count: state
+            .translation_keys
+            .as_ref()
+            .map_or(0, translation::Keys::len),
+        translation_active_key: state
+            .translation_keys
+            .as_ref()
+            .map(translation::Keys::active),
+        ffmpeg_enabled: ffmpeg::is_enabled(),
+        postprocess_enabled: postprocess::is_enabled(),
+        normalize_text: state.normalize_text,
+        normalize_cache_key: state.normalize_cache_key,
+        gzip_espeak: state.gzip_espeak,
+        modes: TTSMode::configured(state).collect(),
+        gtts_ip_rotation,
+        cache_max_capacity: cache.inner.policy().max_capacity(),
+        cache_large_item_bytes: cache.large.as_ref().map(|large| large.min_item_bytes),
+        cache_large_max_bytes: cache.large.as_ref().map(|large| large.max_bytes),
+        cache_min_text_length: state.cache_min_text_length,
+        max_text_length: &state.max_text_length,
+        default_max_length: state.default_max_length,
+        max_length_cap: state.max_length_cap,
+        max_request_timeout_ms: state.max_request_timeout_ms,
+        default_format: &state.default_format,
+        max_body_bytes: state.max_body_bytes,
+    }))
+}
+
 #[derive(serde::Deserialize)]
 struct RefreshCache {
     new_capacity: u64,
@@ -154,21 +549,57 @@ async fn refresh_cache(
 ) -> reqwest::StatusCode {
     let state = STATE.get().unwrap();
 
-    state.cache.store(Arc::new(AudioCache {
-        inner: Cache::new(new_capacity),
-        misses: AtomicU64::new(0),
-        hits: AtomicU64::new(0),
-    }));
+    // The large clip segment is recreated empty with its existing configuration.
+    let large = (state.cache.load().large.as_ref())
+        .map(|large| LargeAudioCache::new(large.min_item_bytes, large.max_bytes));
+
+    state
+        .cache
+        .store(Arc::new(AudioCache::new(Cache::new(new_capacity), large)));
 
     StatusCode::OK
 }
 
+/// How a `/tts` request interacts with the cache.
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum CacheMode {
+    #[default]
+    Normal,
+    /// Only returns cached audio, never generating it.
+    ReadOnly,
+    /// Always generates the audio, still caching it.
+    WriteOnly,
+    /// Always generates the audio, without caching it.
+    Bypass,
+}
+
+/// A gCloud voice clone key, which is a credential so is redacted from logs.
+#[derive(serde::Deserialize)]
+#[serde(transparent)]
+struct VoiceCloneKey(FixedString);
+
+impl std::ops::Deref for VoiceCloneKey {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for VoiceCloneKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("VoiceCloneKey(<redacted>)")
+    }
+}
+
 #[derive(serde::Deserialize, Debug)]
+#[expect(clippy::struct_excessive_bools)]
 struct GetTTS {
     text: FixedString,
     mode: TTSMode,
-    #[serde(rename = "lang")]
-    voice: FixedString<u8>,
+    #[serde(rename = "lang", default)]
+    voice: Option<FixedString<u8>>,
     #[serde(default)]
     speaking_rate: Option<f32>,
     max_length: Option<u64>,
@@ -176,10 +607,101 @@ struct GetTTS {
     preferred_format: Option<FixedString<u8>>,
     #[serde(default)]
     translation_lang: Option<FixedString<u8>>,
+    #[serde(default)]
+    style: Option<FixedString<u8>>,
+    #[serde(default)]
+    no_cache: bool,
+    #[serde(default)]
+    no_store: bool,
+    #[serde(default)]
+    cache_mode: CacheMode,
+    #[serde(default)]
+    best_effort: bool,
+    #[serde(default)]
+    strict_language: bool,
+    #[serde(default)]
+    compare_cache: bool,
+    #[serde(default)]
+    replace_cache: bool,
+    #[serde(default)]
+    ipa: bool,
+    #[serde(default)]
+    sample_rate_hz: Option<u32>,
+    #[serde(default)]
+    trim_silence: bool,
+    #[serde(default)]
+    target_lufs: Option<f32>,
+    #[serde(default)]
+    digits_mode: bool,
+    /// A JSON array of gCloud custom pronunciations.
+    #[serde(default)]
+    custom_pronunciations: Option<FixedString>,
+    /// A gCloud instant custom voice key, used instead of a named voice.
+    #[serde(default)]
+    voice_clone_key: Option<VoiceCloneKey>,
+    #[serde(default)]
+    data_uri: bool,
 }
 
-#[expect(clippy::too_many_lines)]
 async fn get_tts(
+    query: axum::extract::Query<GetTTS>,
+    headers: axum::http::HeaderMap,
+) -> ResponseResult<Response<axum::body::Body>> {
+    chaos::inject_failure().await?;
+
+    let state = STATE.get().unwrap();
+    let timeout = headers
+        .get("X-Timeout-Ms")
+        .and_then(|timeout| timeout.to_str().ok()?.parse().ok())
+        .map(|timeout_ms: u64| Duration::from_millis(timeout_ms.min(state.max_request_timeout_ms)));
+
+    let response = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, get_tts_response(query, headers))
+            .await
+            .unwrap_or(Err(Error::Timeout)),
+        None => get_tts_response(query, headers).await,
+    };
+
+    match response {
+        Err(err @ (Error::UnknownVoice(_) | Error::TextTooLong(_))) => speak_error(err).await,
+        result => result,
+    }
+}
+
+/// Speaks the error with the `SPOKEN_ERRORS` mode and voice, if enabled.
+///
+/// This synthesizes directly instead of going through `get_tts`, so a failure cannot recurse,
+/// and the original error is returned instead.
+async fn speak_error(err: Error) -> ResponseResult<Response<axum::body::Body>> {
+    let state = STATE.get().unwrap();
+    let Some(SpokenErrors { mode, voice }) = &state.spoken_errors else {
+        return Err(err);
+    };
+
+    let text = str_to_fixedstring(err.to_string());
+    let options = SynthesisOptions::default();
+    let (audio, content_type) = match mode
+        .synthesize(state, text, voice, &options, Arc::default())
+        .await
+    {
+        Ok(synthesized) => synthesized,
+        Err(synthesis_err) => {
+            tracing::warn!("Failed to speak error \"{err}\": {synthesis_err:?}");
+            return Err(err);
+        }
+    };
+
+    let sha256 = format!("{:x}", sha2::Sha256::digest(&audio));
+    let mut response = mode.into_response(audio, content_type, HeaderValue::from_str(&sha256)?);
+    response
+        .headers_mut()
+        .insert("X-TTS-Error", HeaderValue::from(u16::from(err.code())));
+
+    Ok(response)
+}
+
+#[expect(clippy::too_many_lines)]
+async fn get_tts_response(
     axum::extract::Query(payload): axum::extract::Query<GetTTS>,
     headers: axum::http::HeaderMap,
 ) -> ResponseResult<Response<axum::body::Body>> {
@@ -187,48 +709,299 @@ async fn get_tts(
         tracing::debug!("Recieved request to TTS: {payload:?}");
     }
 
+    // Captured up front so slow requests can be correlated with what was requested.
+    let cache_status = OnceLock::new();
+    let request_mode = payload.mode;
+    let request_voice = payload.voice.clone().unwrap_or_default();
+    let request_text_length = payload.text.chars().count();
+    let request_text = tracing::enabled!(tracing::Level::DEBUG).then(|| payload.text.clone());
+
     let hit_any_deadline = Arc::new(AtomicBool::new(false));
-    let _guard = DeadlineMonitor::new(
-        Duration::from_millis(5000),
-        hit_any_deadline.clone(),
-        |took| {
-            tracing::warn!("get_tts took {} millis!", took.as_millis());
-        },
-    );
+    let _guard = DeadlineMonitor::new(Duration::from_secs(5), hit_any_deadline.clone(), |took| {
+        tracing::warn!(
+            "get_tts took {} millis! (mode: {request_mode}, voice: {request_voice}, text length: {request_text_length}, cache: {})",
+            took.as_millis(),
+            cache_status.get().copied().unwrap_or("NONE"),
+        );
 
-    let state = STATE.get().unwrap();
-    if let Some(auth_key) = state.auth_key.as_deref() {
-        let auth_header = headers.get("Authorization");
-        if auth_header.map(HeaderValue::to_str).transpose()? != Some(auth_key) {
-            return Err(Error::Unauthorized);
+        if let Some(text) = request_text {
+            tracing::debug!("Slow get_tts request text: {text}");
         }
+    });
+
+    let state = STATE.get().unwrap();
+    let auth_label = check_auth(state, &headers)?;
+    if let Some(auth_label) = auth_label {
+        tracing::debug!("Recieved TTS request using the {auth_label} auth key");
     }
 
-    let translation_lang = payload.translation_lang;
-    let preferred_format = payload.preferred_format;
+    let skip_lookup =
+        payload.no_cache || matches!(payload.cache_mode, CacheMode::WriteOnly | CacheMode::Bypass);
+    if (skip_lookup || payload.compare_cache) && auth_label.is_none() {
+        // Bypassing the cache forces a (possibly expensive) synthesis, so only allow it
+        // when the service is guarded by an auth key.
+        return Err(Error::Unauthorized);
+    }
+
+    let voice = state.requested_voice(payload.mode, payload.voice)?;
+    let (mode, voice) = voice_aliases::resolve(&state.voice_aliases, payload.mode, voice);
+
+    // `none` explicitly skips translation, and leaves the cache key untranslated.
+    let translation_lang = payload
+        .translation_lang
+        .filter(|lang| !lang.eq_ignore_ascii_case("none"));
+    let preferred_format = payload
+        .preferred_format
+        .or_else(|| state.default_format.get(mode).clone());
     let speaking_rate = payload.speaking_rate;
     let mut text = payload.text;
-    let voice = payload.voice;
-    let mode = payload.mode;
+    if state.normalize_text {
+        if let Cow::Owned(normalized) = text::normalize(&text) {
+            text = str_to_fixedstring(normalized);
+        }
+    }
 
-    mode.check_speaking_rate(speaking_rate)?;
-    mode.check_voice(state, &voice).await?;
+    let text_length = text.chars().count();
+
+    if let Some(max_text_length) = *state.max_text_length.get(mode) {
+        if text_length > max_text_length {
+            return Err(Error::TextTooLong(max_text_length));
+        }
+    }
 
-    let mut cache_key = format!("{text} {voice} {mode} {}", speaking_rate.unwrap_or(0.0));
+    let sample_rate = payload.sample_rate_hz;
 
-    if let Some(preferred_format) = &preferred_format {
-        cache_key.push(' ');
-        cache_key.push_str(preferred_format);
+    mode.check_speaking_rate(speaking_rate)?;
+    match payload.voice_clone_key {
+        Some(_) => mode.check_voice_clone(state, &voice).await?,
+        None => mode.check_voice(state, &voice).await?,
     }
+    mode.check_sample_rate(sample_rate, preferred_format.as_deref())?;
 
-    if let Some(translation_lang) = &translation_lang {
-        cache_key.push(' ');
-        cache_key.push_str(translation_lang);
+    if let Some(target_lufs) = payload.target_lufs {
+        if !ffmpeg::LOUDNESS_RANGE.contains(&target_lufs) {
+            return Err(Error::InvalidTargetLoudness(target_lufs));
+        }
     }
 
+    let custom_pronunciations = match payload.custom_pronunciations.as_deref() {
+        Some(raw) => mode.check_custom_pronunciations(raw)?,
+        None => Vec::new(),
+    };
+
+    let style = match payload.style.as_deref() {
+        Some(style) => Some(mode.check_style(state, &voice, style).await?),
+        None => None,
+    };
+
+    // Translated text is in the translation language, not the language it was sent in.
+    if payload.strict_language && translation_lang.is_none() {
+        check_language(state, mode, &voice, &text).await?;
+    }
+
+    // Only transcribed once the cache has been checked, as it may already have the IPA.
+    let ipa_text = (payload.ipa && matches!(mode, TTSMode::eSpeak)).then(|| text.clone());
+    let ipa_requested = ipa_text.is_some();
+
+    // Requests without a `max_length` get the default, and explicit ones are still capped.
+    let max_length = payload
+        .max_length
+        .or(state.default_max_length)
+        .map(|max_length| {
+            state
+                .max_length_cap
+                .map_or(max_length, |cap| max_length.min(cap))
+        })
+        .or(state.max_length_cap);
+
+    let original_text = translation_lang.is_some().then(|| text.clone());
+    let accepts_gzip = gzip::is_accepted(&headers);
+    let requested_range = headers.get(axum::http::header::RANGE).cloned();
+    let data_uri = payload.data_uri;
+    let explicit_max_length = payload.max_length.is_some();
+    let respond = move |synthesized: SynthesizedAudio, cache_status| -> ResponseResult<Response> {
+        let mut audio = synthesized.audio;
+        // Only explicit `max_length`s opt out of gzip, as otherwise `DEFAULT_MAX_LENGTH_SECS`
+        // would stop gzipped audio from ever being served.
+        let serve_gzipped =
+            synthesized.gzipped && accepts_gzip && !explicit_max_length && !data_uri;
+        if synthesized.gzipped && !serve_gzipped {
+            audio = Bytes::from(gzip::decompress(&audio)?);
+        }
+
+        if !serve_gzipped {
+            mode.check_length(&audio, max_length)?;
+        } else if max_length.is_some() {
+            mode.check_length(&gzip::decompress(&audio)?, max_length)?;
+        }
+
+        let audio = chaos::truncate_audio(audio);
+
+        // Ranges of gzipped audio would be of the compressed bytes, so are not supported.
+        let full_len = audio.len();
+        let byte_range = if serve_gzipped || data_uri {
+            range::ByteRange::Full
+        } else {
+            range::parse(requested_range.as_ref(), full_len)
+        };
+
+        let (audio, content_range) = match byte_range {
+            range::ByteRange::Full => (audio, None),
+            range::ByteRange::Partial(range) => {
+                let content_range = format!("bytes {}-{}/{full_len}", range.start, range.end - 1);
+                (audio.slice(range), Some(content_range))
+            }
+            range::ByteRange::Unsatisfiable => {
+                let content_range = format!("bytes */{full_len}");
+                return Ok(Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(axum::http::header::CONTENT_RANGE, content_range)
+                    .body(axum::body::Body::empty())
+                    .unwrap());
+            }
+        };
+
+        let (audio, content_type) = if data_uri {
+            let audio_type = synthesized
+                .content_type
+                .as_ref()
+                .and_then(|content_type| content_type.to_str().ok())
+                .unwrap_or(mode.default_content_type());
+
+            let encoded = base64::engine::general_purpose::STANDARD.encode(&audio);
+            (
+                Bytes::from(format!("data:{audio_type};base64,{encoded}")),
+                Some(HeaderValue::from_static("text/plain; charset=utf-8")),
+            )
+        } else {
+            (audio, synthesized.content_type)
+        };
+
+        let mut response = mode.into_response(audio, content_type, synthesized.sha256);
+        if let Some(content_range) = content_range {
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            response.headers_mut().insert(
+                axum::http::header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range)?,
+            );
+        }
+
+        let headers = response.headers_mut();
+        if let Some(ipa) = synthesized.ipa.filter(|_| ipa_requested) {
+            headers.insert("X-IPA", ipa);
+        }
+        if !serve_gzipped && !data_uri {
+            headers.insert(
+                axum::http::header::ACCEPT_RANGES,
+                HeaderValue::from_static("bytes"),
+            );
+        }
+
+        if synthesized.gzipped {
+            headers.insert(
+                axum::http::header::VARY,
+                HeaderValue::from_static("Accept-Encoding"),
+            );
+        }
+
+        if serve_gzipped {
+            headers.insert(
+                axum::http::header::CONTENT_ENCODING,
+                HeaderValue::from_static("gzip"),
+            );
+        }
+
+        headers.insert("X-Cache", HeaderValue::from_static(cache_status));
+
+        if original_text.is_some() {
+            let translated = if synthesized.translated {
+                "true"
+            } else {
+                "false"
+            };
+            headers.insert("X-Translated", HeaderValue::from_static(translated));
+        }
+
+        if let Some(original_text) = original_text.filter(|_| synthesized.translated) {
+            let original_text = utf8_percent_encode(&original_text, NON_ALPHANUMERIC);
+            headers.insert(
+                "X-Original-Text",
+                HeaderValue::from_str(&original_text.to_string())?,
+            );
+        }
+
+        Ok(response)
+    };
+
+    let trim_silence = payload.trim_silence && ffmpeg::is_enabled();
+    let target_lufs = payload.target_lufs.filter(|_| ffmpeg::is_enabled());
+    let digits_mode = payload.digits_mode;
+    let options = SynthesisOptions {
+        speaking_rate,
+        preferred_format: preferred_format.as_deref(),
+        style,
+        sample_rate,
+        digits_mode,
+        custom_pronunciations: &custom_pronunciations,
+        voice_clone_key: payload.voice_clone_key.as_deref(),
+    };
+
+    let key_suffix = cache_key_suffix(
+        state,
+        mode,
+        &voice,
+        &options,
+        translation_lang.as_deref(),
+        trim_silence,
+        target_lufs,
+    )?;
+
+    let mut cache_key = cache_key_text(state, &text).into_owned();
+    cache_key.push_str(&key_suffix);
+
+    // If the text turns out not to need translating, the audio is the same as if
+    // translation was never requested, so is shared with untranslated requests.
+    let untranslated_hash = if translation_lang.is_some() {
+        let mut untranslated_key = cache_key_text(state, &text).into_owned();
+        untranslated_key.push_str(&cache_key_suffix(
+            state,
+            mode,
+            &voice,
+            &options,
+            None,
+            trim_silence,
+            target_lufs,
+        )?);
+
+        Some(sha2::Sha256::digest(&untranslated_key))
+    } else {
+        None
+    };
+
     tracing::debug!("Recieved request to TTS: {cache_key}");
 
-    let cache_hash = {
+    // Ignores the options which only change how the audio sounds, see `best_effort`.
+    let loose_hash = sha2::Sha256::digest(format!(
+        "{} {voice} {mode} {}",
+        cache_key_text(state, &text),
+        translation_lang.as_deref().unwrap_or("none")
+    ));
+
+    // Returned with every response, so clients can key their own caches identically.
+    let cache_hash = sha2::Sha256::digest(&cache_key);
+    let cache_key_header = HeaderValue::from_str(&format!("{cache_hash:x}"))?;
+    let respond = |synthesized, cache_status| -> ResponseResult<Response> {
+        let mut response = respond(synthesized, cache_status)?;
+        response
+            .headers_mut()
+            .insert("X-Cache-Key", cache_key_header.clone());
+
+        Ok(response)
+    };
+
+    // The cached audio to compare the fresh synthesis against, if `compare_cache` is set.
+    let mut compare_with = None;
+    'lookup: {
         let _guard = DeadlineMonitor::new(
             Duration::from_millis(50),
             hit_any_deadline.clone(),
@@ -240,70 +1013,221 @@ async fn get_tts(
             },
         );
 
-        let cache_hash = sha2::Sha256::digest(&cache_key);
+        if let Some(hot_keys) = &state.hot_keys {
+            hot_keys.record(&cache_hash);
+        }
+
+        if payload.compare_cache {
+            tracing::debug!("Comparing fresh audio against the cache for {cache_key}");
+            compare_with = Some(state.cache.load().get(&cache_hash));
+            break 'lookup;
+        }
+
+        if skip_lookup {
+            tracing::debug!("Skipping cache lookup for {cache_key}");
+            break 'lookup;
+        }
+
         let audio_cache = state.cache.load();
-        if let Some(cached_audio) = audio_cache.inner.get(&cache_hash) {
+        if let Some(mut cached_audio) = audio_cache.get(&cache_hash) {
             audio_cache.hits.fetch_add(1, Ordering::Relaxed);
-
-            mode.check_length(&cached_audio, payload.max_length)?;
+            if let Some(ipa_text) = &ipa_text {
+                add_ipa(&mut cached_audio, ipa_text, &voice).await?;
+            }
 
             tracing::debug!("Used cached TTS for {cache_key}");
-            return Ok(mode.into_response(cached_audio, None));
+            let _ = cache_status.set("HIT");
+            return respond(cached_audio, "HIT");
         }
 
         audio_cache.misses.fetch_add(1, Ordering::Relaxed);
-        cache_hash
-    };
+        if payload.cache_mode == CacheMode::ReadOnly {
+            let _ = cache_status.set("MISS");
+            return Err(Error::NotCached);
+        }
+    }
 
-    if let Some(language) = translation_lang {
-        let Some(token) = &state.translation_key else {
-            return Err(Error::TranslationDisabled);
+    // Concurrent requests for the same audio wait on a single synthesis, instead of
+    // all hitting the backend before the first result makes it into the cache.
+    let (inflight, _inflight_guard) = InflightGuard::new(&state.inflight, cache_hash);
+    let synthesize = || async {
+        // Held until the audio is synthesized, bounding the memory used by concurrent syntheses.
+        let _budget_permit = match &state.audio_budget {
+            Some(budget) => Some(budget.try_acquire(mode.estimate_audio_bytes(text_length))?),
+            None => None,
         };
 
-        let _guard = DeadlineMonitor::new(
-            Duration::from_millis(200),
-            hit_any_deadline.clone(),
-            |took| {
-                tracing::warn!("Fetching translation took {} millis!", took.as_millis());
-            },
-        );
-
-        if let Some(translated) = translation::run(&state.reqwest, token, &text, &language).await? {
-            text = translated;
+        let mut translated = false;
+        if let Some(language) = translation_lang {
+            let Some(keys) = &state.translation_keys else {
+                return Err(Error::TranslationDisabled);
+            };
+
+            let _guard = DeadlineMonitor::new(
+                Duration::from_millis(200),
+                hit_any_deadline.clone(),
+                |took| {
+                    tracing::warn!("Fetching translation took {} millis!", took.as_millis());
+                },
+            );
+
+            if let Some(translated_text) = translation::run(
+                &state.reqwest,
+                keys,
+                &text,
+                &language,
+                &state.translation_skip_langs,
+            )
+            .await?
+            {
+                text = translated_text;
+                translated = true;
+            }
         }
-    };
 
-    let (audio, content_type) = match mode {
-        TTSMode::gTTS => {
-            gtts::get_tts(&state.gtts, &text, &voice, hit_any_deadline.clone()).await?
+        if let Some(untranslated_hash) = untranslated_hash.filter(|_| !translated) {
+            let cached = (!skip_lookup && !payload.compare_cache)
+                .then(|| state.cache.load().get(&untranslated_hash))
+                .flatten();
+
+            if let Some(cached_audio) = cached {
+                tracing::debug!("Used cached untranslated TTS for {cache_key}");
+                return Ok(cached_audio);
+            }
         }
-        TTSMode::eSpeak => {
-            espeak::get_tts(&text, &voice, speaking_rate.map_or(0, |r| r as u16)).await?
+
+        // SSML backends use `say-as`, so only the other backends need the digits spacing out.
+        if digits_mode && matches!(mode, TTSMode::gTTS | TTSMode::eSpeak | TTSMode::ElevenLabs) {
+            if let Cow::Owned(spaced) = text::space_digits(&text) {
+                text = str_to_fixedstring(spaced);
+            }
         }
-        TTSMode::Polly => {
-            polly::get_tts(
-                &state.polly,
-                text,
-                &voice,
-                speaking_rate.map(|r| r as u8),
-                preferred_format.as_deref(),
+
+        // Checked before synthesis, as Polly takes ownership of the text.
+        let has_speech = text.chars().any(char::is_alphanumeric);
+
+        let joined = match &state.sentence_cache {
+            Some(sentence_cache) => {
+                sentence_cache
+                    .synthesize(
+                        state,
+                        mode,
+                        &text,
+                        &voice,
+                        &options,
+                        &key_suffix,
+                        hit_any_deadline.clone(),
+                    )
+                    .await?
+            }
+            None => None,
+        };
+
+        let (mut audio, mut content_type) = match joined {
+            Some(joined) => joined,
+            None => {
+                mode.synthesize(state, text, &voice, &options, hit_any_deadline.clone())
+                    .await?
+            }
+        };
+
+        // Backends sometimes "succeed" with an empty clip, which should not be cached.
+        if has_speech && audio.len() < MIN_AUDIO_BYTES {
+            return Err(anyhow::anyhow!(
+                "{mode} returned only {} bytes of audio for {text_length} characters of text",
+                audio.len()
             )
-            .await?
+            .into());
         }
-        TTSMode::gCloud => {
-            gcloud::get_tts(
-                &state.gcloud,
-                &text,
-                &voice,
-                speaking_rate.unwrap_or(0.0),
-                preferred_format.as_deref(),
-            )
-            .await?
+
+        // Backends don't always return the format they advertise, so check the actual audio.
+        if let Some(sniffed) = sniff::content_type(&audio) {
+            let advertised = content_type
+                .as_ref()
+                .and_then(|c| c.to_str().ok())
+                .unwrap_or(mode.default_content_type());
+
+            if advertised.split(';').next().map(str::trim) != Some(sniffed) {
+                tracing::warn!("{mode} returned {sniffed} audio, but advertised {advertised}");
+                content_type = Some(HeaderValue::from_static(sniffed));
+            }
+        }
+
+        if trim_silence {
+            if let Some(format) = mode.audio_format(content_type.as_ref()) {
+                audio = ffmpeg::trim_silence(&audio, format).await?;
+            } else {
+                tracing::debug!("Cannot trim silence from {content_type:?} audio");
+            }
         }
+
+        if let Some(target_lufs) = target_lufs {
+            if let Some(format) = mode.audio_format(content_type.as_ref()) {
+                audio = ffmpeg::normalize_loudness(&audio, target_lufs, format).await?;
+            } else {
+                tracing::debug!("Cannot normalize the loudness of {content_type:?} audio");
+            }
+        }
+
+        let (audio, content_type) = postprocess::run(audio, content_type).await?;
+
+        let sha256 = format!("{:x}", sha2::Sha256::digest(&audio));
+
+        // WAV is uncompressed, so is stored gzipped to save cache memory and bandwidth.
+        let gzipped = state.gzip_espeak
+            && matches!(mode, TTSMode::eSpeak)
+            && mode.audio_format(content_type.as_ref()) == Some(ffmpeg::Format::Wav);
+        let audio = if gzipped {
+            let compressed = tokio::task::spawn_blocking(move || gzip::compress(&audio)).await??;
+            Bytes::from(compressed)
+        } else {
+            audio
+        };
+
+        ResponseResult::Ok(SynthesizedAudio {
+            sha256: HeaderValue::from_str(&sha256)?,
+            audio,
+            content_type,
+            translated,
+            gzipped,
+            ipa: None,
+        })
+    };
+
+    let _ = cache_status.set("MISS");
+    let mut synthesized = match inflight.get_or_try_init(synthesize).await {
+        Ok(synthesized) => synthesized.clone(),
+        Err(err) if payload.best_effort => {
+            let Some(fallback) = state.cache.load().get_loose(&loose_hash) else {
+                return Err(err);
+            };
+
+            tracing::warn!("Falling back to cached audio with different options: {err}");
+            let mut response = respond(fallback, "HIT")?;
+            response
+                .headers_mut()
+                .insert("X-Degraded", HeaderValue::from_static("true"));
+
+            return Ok(response);
+        }
+        Err(err) => return Err(err),
     };
 
     tracing::debug!("Generated TTS from {cache_key}");
-    {
+    if let Some(ipa_text) = &ipa_text {
+        add_ipa(&mut synthesized, ipa_text, &voice).await?;
+    }
+
+    let compare_status = compare_with.map(|cached| match cached {
+        Some(cached) if cached.sha256 == synthesized.sha256 => "MATCH",
+        Some(_) => "MISMATCH",
+        None => "NONE",
+    });
+
+    let store = !payload.no_store
+        && payload.cache_mode != CacheMode::Bypass
+        && (!payload.compare_cache || payload.replace_cache);
+    if store && text_length >= state.cache_min_text_length {
         let _guard = DeadlineMonitor::new(
             Duration::from_millis(50),
             hit_any_deadline.clone(),
@@ -312,63 +1236,460 @@ async fn get_tts(
             },
         );
 
-        tracing::debug!("Cached {} kb of audio", (audio.len() as f64) / 1024.0);
-        state.cache.load().inner.insert(cache_hash, audio.clone());
+        let audio_len = synthesized.audio.len();
+        tracing::debug!("Cached {} kb of audio", (audio_len as f64) / 1024.0);
+        let audio_cache = state.cache.load();
+        if let Some(untranslated_hash) = untranslated_hash.filter(|_| !synthesized.translated) {
+            audio_cache.insert(untranslated_hash, loose_hash, synthesized.clone());
+        }
+
+        audio_cache.insert(cache_hash, loose_hash, synthesized.clone());
+    }
+
+    let mut response = respond(synthesized, "MISS")?;
+    if let Some(compare_status) = compare_status {
+        tracing::debug!("Fresh audio compared against the cache: {compare_status}");
+        response
+            .headers_mut()
+            .insert("X-Cache-Compare", HeaderValue::from_static(compare_status));
+    }
+
+    Ok(response)
+}
+
+/// Rejects the text if it is not in one of the voice's languages, see `strict_language`.
+async fn check_language(
+    state: &State,
+    mode: TTSMode,
+    voice: &str,
+    text: &str,
+) -> ResponseResult<()> {
+    let Some(detector) = &state.language_detector else {
+        return Err(Error::TranslationDisabled);
+    };
+
+    let Some(voice_languages) = mode.voice_languages(state, voice).await? else {
+        return Ok(());
     };
 
-    mode.check_length(&audio, payload.max_length)?;
-    Ok(mode.into_response(audio, content_type))
+    let Some(detected) = detector.detect(state, text).await? else {
+        return Ok(());
+    };
+
+    if voice_languages.contains(&detected) {
+        Ok(())
+    } else {
+        Err(Error::LanguageMismatch(
+            format!("The text is in {detected}, which {voice} does not speak").into_boxed_str(),
+        ))
+    }
+}
+
+/// The text part of the cache key, only normalized for the key so the text is still synthesized as it was sent.
+fn cache_key_text<'a>(state: &State, text: &'a str) -> Cow<'a, str> {
+    if state.normalize_cache_key {
+        Cow::Owned(text::cache_key(text))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// The part of the cache key after the text, covering everything else which changes the audio.
+fn cache_key_suffix(
+    state: &State,
+    mode: TTSMode,
+    voice: &str,
+    options: &SynthesisOptions<'_>,
+    translation_lang: Option<&str>,
+    trim_silence: bool,
+    target_lufs: Option<f32>,
+) -> Result<String> {
+    let mut suffix = format!(" {voice} {mode} {}", options.speaking_rate.unwrap_or(0.0));
+
+    if let Some(preferred_format) = options.preferred_format {
+        suffix.push(' ');
+        suffix.push_str(preferred_format);
+    }
+
+    if let Some(translation_lang) = translation_lang {
+        suffix.push(' ');
+        suffix.push_str(translation_lang);
+
+        // Changing which languages are skipped changes which text is translated.
+        for skip_lang in &state.translation_skip_langs {
+            suffix.push_str(" skip:");
+            suffix.push_str(skip_lang);
+        }
+    }
+
+    if let Some(style) = options.style {
+        suffix.push(' ');
+        suffix.push_str(style.as_str());
+    }
+
+    if let Some(sample_rate) = options.sample_rate {
+        suffix.push(' ');
+        suffix.push_str(&sample_rate.to_arraystring());
+    }
+
+    if trim_silence {
+        suffix.push_str(" trim_silence");
+    }
+
+    if let Some(target_lufs) = target_lufs {
+        write!(suffix, " lufs={target_lufs}")?;
+    }
+
+    if options.digits_mode {
+        suffix.push_str(" digits_mode");
+    }
+
+    if !options.custom_pronunciations.is_empty() {
+        suffix.push(' ');
+        suffix.push_str(&serde_json::to_string(options.custom_pronunciations)?);
+    }
+
+    // The cache key is logged, so only includes a digest of the clone key.
+    if let Some(voice_clone_key) = options.voice_clone_key {
+        write!(suffix, " clone:{:x}", sha2::Sha256::digest(voice_clone_key))?;
+    }
+
+    Ok(suffix)
+}
+
+/// The options passed to the backends, other than the text and voice.
+#[derive(Default)]
+struct SynthesisOptions<'a> {
+    speaking_rate: Option<f32>,
+    preferred_format: Option<&'a str>,
+    style: Option<polly::Style>,
+    sample_rate: Option<u32>,
+    digits_mode: bool,
+    custom_pronunciations: &'a [gcloud::CustomPronunciation],
+    voice_clone_key: Option<&'a str>,
 }
 
-#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 enum TTSMode {
     gTTS,
     Polly,
     eSpeak,
     gCloud,
+    ElevenLabs,
 }
 
 impl TTSMode {
+    const ALL: [Self; 5] = [
+        Self::gTTS,
+        Self::Polly,
+        Self::eSpeak,
+        Self::gCloud,
+        Self::ElevenLabs,
+    ];
+
     fn into_response(
         self,
         data: Bytes,
         content_type: Option<reqwest::header::HeaderValue>,
+        sha256: HeaderValue,
     ) -> Response {
         Response::builder()
             .header(
                 axum::http::header::CONTENT_TYPE,
-                content_type.unwrap_or_else(|| {
-                    HeaderValue::from_static(match self {
-                        Self::gTTS => "audio/mpeg",
-                        Self::eSpeak => "audio/wav",
-                        Self::gCloud => "audio/opus",
-                        Self::Polly => "audio/ogg",
-                    })
-                }),
+                content_type
+                    .unwrap_or_else(|| HeaderValue::from_static(self.default_content_type())),
             )
+            .header("X-Audio-SHA256", sha256)
             .body(axum::body::Body::from(data))
             .unwrap()
     }
 
+    /// The modes which can be used, skipping those missing their credentials.
+    fn configured(state: &State) -> impl Iterator<Item = Self> + '_ {
+        Self::ALL.into_iter().filter(|mode| match mode {
+            Self::ElevenLabs => state.elevenlabs.is_configured(),
+            Self::gTTS | Self::Polly | Self::eSpeak | Self::gCloud => true,
+        })
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(name))
+    }
+
+    async fn voices(self, state: &State) -> Result<Vec<String>> {
+        Ok(match self {
+            Self::gTTS => gtts::get_voices(),
+            Self::eSpeak => espeak::get_voices().to_vec(),
+            Self::Polly => polly::get_voices(&state.polly).await?,
+            Self::gCloud => gcloud::get_voices(&state.gcloud).await?,
+            Self::ElevenLabs => elevenlabs::get_voices(&state.elevenlabs).await?,
+        })
+    }
+
+    /// Synthesizes the text with the backend for this mode, without any caching or post-processing.
+    async fn synthesize(
+        self,
+        state: &State,
+        text: FixedString,
+        voice: &str,
+        options: &SynthesisOptions<'_>,
+        hit_any_deadline: Arc<AtomicBool>,
+    ) -> Result<(Bytes, Option<HeaderValue>)> {
+        let SynthesisOptions {
+            speaking_rate,
+            preferred_format,
+            style,
+            sample_rate,
+            digits_mode,
+            custom_pronunciations,
+            voice_clone_key,
+        } = *options;
+
+        let result = match self {
+            Self::gTTS => gtts::get_tts(&state.gtts, &text, voice, hit_any_deadline).await,
+            Self::eSpeak => {
+                espeak::get_tts(
+                    &text,
+                    voice,
+                    speaking_rate.map_or(0, |r| r as u16),
+                    sample_rate,
+                    preferred_format,
+                )
+                .await
+            }
+            Self::Polly => {
+                polly::get_tts(
+                    &state.polly,
+                    text,
+                    voice,
+                    speaking_rate.map(|r| r as u16),
+                    preferred_format,
+                    style,
+                    sample_rate,
+                    digits_mode,
+                )
+                .await
+            }
+            Self::gCloud => {
+                gcloud::get_tts(
+                    &state.gcloud,
+                    &text,
+                    voice,
+                    speaking_rate.unwrap_or(0.0),
+                    preferred_format,
+                    digits_mode,
+                    custom_pronunciations,
+                    voice_clone_key,
+                )
+                .await
+            }
+            Self::ElevenLabs => {
+                elevenlabs::get_tts(&state.elevenlabs, &text, voice, preferred_format).await
+            }
+        };
+
+        // Text being too long is the request's fault, not the backend's.
+        let healthy = match &result {
+            Ok(_) => true,
+            Err(err) => err.is::<TextTooLong>(),
+        };
+        state.healthy.get(self).store(healthy, Ordering::Relaxed);
+
+        result
+    }
+
+    const fn default_content_type(self) -> &'static str {
+        match self {
+            Self::gTTS | Self::ElevenLabs => "audio/mpeg",
+            Self::eSpeak => "audio/wav",
+            Self::gCloud => "audio/opus",
+            Self::Polly => "audio/ogg",
+        }
+    }
+
+    /// The format of audio returned by this mode, for post-processing with ffmpeg.
+    fn audio_format(self, content_type: Option<&HeaderValue>) -> Option<ffmpeg::Format> {
+        let content_type = match content_type {
+            Some(content_type) => content_type.to_str().ok()?,
+            None => self.default_content_type(),
+        };
+
+        ffmpeg::Format::from_content_type(content_type)
+    }
+
     async fn check_voice(self, state: &State, voice: &str) -> ResponseResult<()> {
         if match self {
             Self::gTTS => gtts::check_voice(voice),
             Self::eSpeak => espeak::check_voice(voice),
             Self::gCloud => gcloud::check_voice(&state.gcloud, voice).await?,
             Self::Polly => polly::check_voice(&state.polly, voice).await?,
+            Self::ElevenLabs => elevenlabs::check_voice(&state.elevenlabs, voice).await?,
         } {
             Ok(())
         } else {
-            Err(Error::UnknownVoice(
-                format!("Unknown voice: {voice}").into_boxed_str(),
+            Err(Error::UnknownVoice(
+                format!("Unknown voice: {voice}").into_boxed_str(),
+            ))
+        }
+    }
+
+    async fn check_style(
+        self,
+        state: &State,
+        voice: &str,
+        style: &str,
+    ) -> ResponseResult<polly::Style> {
+        if !matches!(self, Self::Polly) {
+            return Err(Error::InvalidStyle(
+                format!("Speaking styles are not supported by {self}").into_boxed_str(),
+            ));
+        }
+
+        let Some(parsed_style) = polly::Style::from_str(style) else {
+            return Err(Error::InvalidStyle(
+                format!("Unknown speaking style: {style}").into_boxed_str(),
+            ));
+        };
+
+        if polly::check_style(&state.polly, voice, parsed_style).await? {
+            Ok(parsed_style)
+        } else {
+            Err(Error::InvalidStyle(
+                format!("{voice} does not support the {style} speaking style").into_boxed_str(),
+            ))
+        }
+    }
+
+    /// A rough upper estimate of the audio size generated for the given amount of text.
+    fn estimate_audio_bytes(self, text_length: usize) -> usize {
+        let bytes_per_char = match self {
+            // Uncompressed WAV
+            Self::eSpeak => 2048,
+            Self::gTTS | Self::Polly | Self::gCloud | Self::ElevenLabs => 512,
+        };
+
+        text_length.max(1) * bytes_per_char
+    }
+
+    /// The primary language subtags the voice speaks, or `None` if it can speak any language.
+    async fn voice_languages(self, state: &State, voice: &str) -> Result<Option<Vec<String>>> {
+        Ok(match self {
+            Self::gTTS if voice == gtts::AUTO_VOICE => None,
+            Self::gTTS | Self::gCloud => Some(vec![language::primary_subtag(voice)]),
+            Self::eSpeak => Some(vec![language::espeak_voice_language(voice)]),
+            Self::Polly => Some(
+                polly::voice_languages(&state.polly, voice)
+                    .await?
+                    .iter()
+                    .map(|language| language::primary_subtag(language))
+                    .collect(),
+            ),
+            // ElevenLabs voices are multilingual.
+            Self::ElevenLabs => None,
+        })
+    }
+
+    /// The `preferred_format`s the mode supports.
+    const fn formats(self) -> &'static [&'static str] {
+        match self {
+            Self::Polly => polly::FORMATS,
+            Self::gCloud => gcloud::FORMATS,
+            Self::ElevenLabs => elevenlabs::OUTPUT_FORMATS,
+            Self::eSpeak => espeak::FORMATS,
+            Self::gTTS => &[],
+        }
+    }
+
+    /// If the mode supports the given `preferred_format`.
+    fn check_format(self, format: &str) -> bool {
+        match self {
+            Self::Polly => polly::check_format(format),
+            Self::gCloud => gcloud::check_format(format),
+            Self::ElevenLabs => elevenlabs::check_format(format),
+            Self::eSpeak => espeak::check_format(format),
+            Self::gTTS => false,
+        }
+    }
+
+    fn check_custom_pronunciations(
+        self,
+        raw: &str,
+    ) -> ResponseResult<Vec<gcloud::CustomPronunciation>> {
+        if !matches!(self, Self::gCloud) {
+            return Err(Error::InvalidPronunciations(
+                format!("Custom pronunciations are not supported by {self}").into_boxed_str(),
+            ));
+        }
+
+        serde_json::from_str(raw).map_err(|err| {
+            Error::InvalidPronunciations(
+                format!("Invalid custom pronunciations: {err}").into_boxed_str(),
+            )
+        })
+    }
+
+    /// Checks the mode supports voice clones, and that `voice` is only the clone's language code,
+    /// as a clone cannot be combined with a named voice.
+    async fn check_voice_clone(self, state: &State, voice: &str) -> ResponseResult<()> {
+        if !matches!(self, Self::gCloud) {
+            return Err(Error::InvalidVoiceClone(
+                format!("Voice clones are not supported by {self}").into_boxed_str(),
+            ));
+        }
+
+        if !gcloud::check_language(&state.gcloud, voice).await? {
+            return Err(Error::InvalidVoiceClone(
+                format!(
+                    "{voice} is not a gCloud language code, voice clones require a language code such as en-US"
+                )
+                .into_boxed_str(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn check_sample_rate(
+        self,
+        sample_rate: Option<u32>,
+        preferred_format: Option<&str>,
+    ) -> ResponseResult<()> {
+        let Some(sample_rate) = sample_rate else {
+            return Ok(());
+        };
+
+        let valid = match self {
+            Self::Polly => polly::check_sample_rate(preferred_format, sample_rate),
+            // eSpeak audio has to be resampled to change the sample rate.
+            Self::eSpeak if !ffmpeg::is_enabled() => {
+                return Err(Error::InvalidSampleRate(
+                    "Setting the sample rate for eSpeak requires ffmpeg".into(),
+                ))
+            }
+            Self::eSpeak => espeak::check_sample_rate(preferred_format, sample_rate),
+            Self::gTTS | Self::gCloud | Self::ElevenLabs => {
+                return Err(Error::InvalidSampleRate(
+                    format!("Setting the sample rate is not supported by {self}").into_boxed_str(),
+                ))
+            }
+        };
+
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidSampleRate(
+                format!("{sample_rate}hz is not supported by {self} for the requested format")
+                    .into_boxed_str(),
             ))
         }
     }
 
     fn check_length(self, audio: &[u8], max_length: Option<u64>) -> ResponseResult<()> {
-        if max_length.map_or(true, |max_length| match self {
-            Self::gTTS => check_mp3_length(audio, max_length),
+        if max_length.is_none_or(|max_length| match self {
+            Self::gTTS | Self::ElevenLabs => check_mp3_length(audio, max_length),
             Self::eSpeak => espeak::check_length(audio, max_length as u32),
             Self::gCloud | Self::Polly => true,
         }) {
@@ -385,30 +1706,109 @@ impl TTSMode {
                     return Err(Error::InvalidSpeakingRate(speaking_rate));
                 }
             }
+
+            if let Some(min) = self.min_speaking_rate() {
+                if speaking_rate < min {
+                    return Err(Error::InvalidSpeakingRate(speaking_rate));
+                }
+            }
         }
 
         Ok(())
     }
 
+    const fn min_speaking_rate(self) -> Option<f32> {
+        match self {
+            // Polly's SSML prosody rate only accepts 20% to 200%.
+            Self::Polly => Some(20.0),
+            Self::gTTS | Self::eSpeak | Self::gCloud | Self::ElevenLabs => None,
+        }
+    }
+
     const fn max_speaking_rate(self) -> Option<f32> {
         match self {
-            Self::gTTS => None,
-            Self::Polly => Some(500.0),
+            // ElevenLabs controls the speaking rate through voice settings instead.
+            Self::gTTS | Self::ElevenLabs => None,
+            Self::Polly => Some(200.0),
             Self::eSpeak => Some(400.0),
             Self::gCloud => Some(4.0),
         }
     }
 
+    /// What the speaking rate is measured in, or `None` if the mode does not support it.
+    const fn speaking_rate_unit(self) -> Option<&'static str> {
+        match self {
+            Self::gTTS | Self::ElevenLabs => None,
+            Self::Polly => Some("percent"),
+            Self::eSpeak => Some("words_per_minute"),
+            Self::gCloud => Some("multiplier"),
+        }
+    }
+
     fn as_str(self) -> &'static str {
         match self {
             Self::gTTS => "gTTS",
             Self::Polly => "Polly",
             Self::eSpeak => "eSpeak",
             Self::gCloud => "gCloud",
+            Self::ElevenLabs => "ElevenLabs",
+        }
+    }
+
+    /// The prefix used for per-mode environment variables, such as `GTTS_MAX_TEXT_LENGTH`.
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Self::gTTS => "GTTS",
+            Self::Polly => "POLLY",
+            Self::eSpeak => "ESPEAK",
+            Self::gCloud => "GCLOUD",
+            Self::ElevenLabs => "ELEVENLABS",
+        }
+    }
+}
+
+/// A setting which can be configured differently for each mode.
+#[derive(serde::Serialize)]
+struct PerMode<T> {
+    gtts: T,
+    polly: T,
+    espeak: T,
+    gcloud: T,
+    elevenlabs: T,
+}
+
+impl<T> PerMode<T> {
+    fn from_fn(mut f: impl FnMut(TTSMode) -> T) -> Self {
+        Self {
+            gtts: f(TTSMode::gTTS),
+            polly: f(TTSMode::Polly),
+            espeak: f(TTSMode::eSpeak),
+            gcloud: f(TTSMode::gCloud),
+            elevenlabs: f(TTSMode::ElevenLabs),
+        }
+    }
+
+    fn get(&self, mode: TTSMode) -> &T {
+        match mode {
+            TTSMode::gTTS => &self.gtts,
+            TTSMode::Polly => &self.polly,
+            TTSMode::eSpeak => &self.espeak,
+            TTSMode::gCloud => &self.gcloud,
+            TTSMode::ElevenLabs => &self.elevenlabs,
         }
     }
 }
 
+impl<T: FromStr + Clone> PerMode<Option<T>> {
+    /// Reads `{MODE}_{key}` for each mode, falling back to `key` if unset.
+    fn from_env(key: &str) -> Self {
+        let default = parse_env(key);
+        Self::from_fn(|mode| {
+            parse_env(&format!("{}_{key}", mode.env_prefix())).or_else(|| default.clone())
+        })
+    }
+}
+
 impl Display for TTSMode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(self.as_str())
@@ -424,30 +1824,510 @@ impl serde::Serialize for TTSMode {
     }
 }
 
+type InflightMap = std::sync::Mutex<HashMap<AudioCacheDigest, Arc<InflightSynthesis>>>;
+type InflightSynthesis = tokio::sync::OnceCell<SynthesizedAudio>;
+
+#[derive(Clone)]
+struct SynthesizedAudio {
+    audio: Bytes,
+    content_type: Option<HeaderValue>,
+    /// The hex SHA-256 digest of `audio`, stored so cache hits do not need to rehash it.
+    sha256: HeaderValue,
+    /// If the text was changed by translation before synthesis.
+    translated: bool,
+    /// If `audio` is gzip compressed, see `ESPEAK_GZIP`.
+    gzipped: bool,
+    /// The percent-encoded IPA transcription of the text, if it has been requested, see `ipa`.
+    ipa: Option<HeaderValue>,
+}
+
+/// Transcribes the text to IPA for the `X-IPA` header, unless the audio was cached with it.
+async fn add_ipa(synthesized: &mut SynthesizedAudio, text: &str, voice: &str) -> Result<()> {
+    if synthesized.ipa.is_none() {
+        let ipa = espeak::get_ipa(text, voice).await?;
+        let encoded = utf8_percent_encode(&ipa, NON_ALPHANUMERIC).to_string();
+        synthesized.ipa = Some(HeaderValue::from_str(&encoded)?);
+    }
+
+    Ok(())
+}
+
+/// Tracks a request's interest in an in-progress synthesis, removing it from the
+/// in-flight map once the request is done with it.
+struct InflightGuard<'a> {
+    map: &'a InflightMap,
+    key: AudioCacheDigest,
+    synthesis: Arc<InflightSynthesis>,
+}
+
+impl<'a> InflightGuard<'a> {
+    fn new(map: &'a InflightMap, key: AudioCacheDigest) -> (Arc<InflightSynthesis>, Self) {
+        let synthesis = map.lock().unwrap().entry(key).or_default().clone();
+        let guard = Self {
+            map,
+            key,
+            synthesis: synthesis.clone(),
+        };
+
+        (synthesis, guard)
+    }
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        let mut map = self.map.lock().unwrap();
+        if map
+            .get(&self.key)
+            .is_some_and(|synthesis| Arc::ptr_eq(synthesis, &self.synthesis))
+        {
+            map.remove(&self.key);
+        }
+    }
+}
+
 struct AudioCache {
-    inner: Cache<AudioCacheDigest, Bytes>,
+    inner: Cache<AudioCacheDigest, SynthesizedAudio>,
+    /// A separate segment for large clips, so a burst of them cannot evict the small clips.
+    large: Option<LargeAudioCache>,
+    /// Maps the key of just the text, voice, mode, and translation to the last cached key
+    /// with them, for falling back to when `best_effort` requests fail.
+    loose: Cache<AudioCacheDigest, AudioCacheDigest>,
     misses: AtomicU64,
     hits: AtomicU64,
 }
 
+/// The large clip segment of the audio cache, see `CACHE_LARGE_ITEM_BYTES`.
+struct LargeAudioCache {
+    inner: Cache<AudioCacheDigest, SynthesizedAudio>,
+    min_item_bytes: usize,
+    max_bytes: u64,
+}
+
+impl LargeAudioCache {
+    fn new(min_item_bytes: usize, max_bytes: u64) -> Self {
+        let inner = Cache::builder()
+            .weigher(|_, audio: &SynthesizedAudio| {
+                u32::try_from(audio.audio.len()).unwrap_or(u32::MAX)
+            })
+            .max_capacity(max_bytes)
+            .build();
+
+        Self {
+            inner,
+            min_item_bytes,
+            max_bytes,
+        }
+    }
+
+    fn from_env() -> Option<Self> {
+        let min_item_bytes = parse_env("CACHE_LARGE_ITEM_BYTES")?;
+        let max_bytes = parse_env("CACHE_LARGE_MAX_BYTES").unwrap_or(64 * 1024 * 1024);
+
+        tracing::info!(
+            "Initialised large audio cache for clips of {min_item_bytes} bytes or more, with max size: {max_bytes} bytes"
+        );
+        Some(Self::new(min_item_bytes, max_bytes))
+    }
+}
+
+impl AudioCache {
+    fn new(
+        inner: Cache<AudioCacheDigest, SynthesizedAudio>,
+        large: Option<LargeAudioCache>,
+    ) -> Self {
+        let loose_capacity = inner.policy().max_capacity().unwrap_or(1000);
+        Self {
+            inner,
+            large,
+            loose: Cache::new(loose_capacity),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, key: &AudioCacheDigest) -> Option<SynthesizedAudio> {
+        self.inner
+            .get(key)
+            .or_else(|| self.large.as_ref()?.inner.get(key))
+    }
+
+    fn contains_key(&self, key: &AudioCacheDigest) -> bool {
+        self.inner.contains_key(key)
+            || self
+                .large
+                .as_ref()
+                .is_some_and(|large| large.inner.contains_key(key))
+    }
+
+    /// Finds audio cached with the same loose key, but possibly different options.
+    fn get_loose(&self, loose_key: &AudioCacheDigest) -> Option<SynthesizedAudio> {
+        self.get(&self.loose.get(loose_key)?)
+    }
+
+    /// Inserts the audio into the segment for its size.
+    fn insert(&self, key: AudioCacheDigest, loose_key: AudioCacheDigest, audio: SynthesizedAudio) {
+        self.loose.insert(loose_key, key);
+        match &self.large {
+            Some(large) if audio.audio.len() >= large.min_item_bytes => {
+                large.inner.insert(key, audio);
+            }
+            _ => self.inner.insert(key, audio),
+        }
+    }
+}
+
+/// Limits the total size of audio being synthesized at once, see `MAX_INFLIGHT_AUDIO_BYTES`.
+struct AudioBudget {
+    semaphore: tokio::sync::Semaphore,
+    max_bytes: u32,
+}
+
+impl AudioBudget {
+    fn new(max_bytes: u32) -> Self {
+        Self {
+            semaphore: tokio::sync::Semaphore::new(max_bytes as usize),
+            max_bytes,
+        }
+    }
+
+    fn try_acquire(&self, bytes: usize) -> ResponseResult<tokio::sync::SemaphorePermit<'_>> {
+        // Requests larger than the whole budget are still allowed, just not concurrently.
+        let bytes = u32::try_from(bytes).unwrap_or(u32::MAX).min(self.max_bytes);
+        self.semaphore
+            .try_acquire_many(bytes)
+            .map_err(|_| Error::Overloaded)
+    }
+}
+
+struct AuthKey {
+    key: FixedString<u8>,
+    /// Identifies which client is using the key, without logging the key itself.
+    label: FixedString<u8>,
+}
+
 struct State {
-    auth_key: Option<FixedString<u8>>,
-    translation_key: Option<FixedString<u8>>,
+    auth_keys: Vec<AuthKey>,
+    translation_keys: Option<translation::Keys>,
+    translation_skip_langs: Vec<String>,
     reqwest: reqwest::Client,
 
     cache: ArcSwap<AudioCache>,
+    cache_min_text_length: usize,
+    inflight: InflightMap,
+    audio_budget: Option<AudioBudget>,
+    max_text_length: PerMode<Option<usize>>,
+    default_max_length: Option<u64>,
+    max_length_cap: Option<u64>,
+    /// The longest a request can ask to be given with `X-Timeout-Ms`.
+    max_request_timeout_ms: u64,
+    default_format: PerMode<Option<FixedString<u8>>>,
+    default_voice: PerMode<Option<FixedString<u8>>>,
+    /// If the last synthesis with each mode succeeded, see `/modes?detailed=true`.
+    healthy: PerMode<AtomicBool>,
+    max_body_bytes: usize,
+    normalize_text: bool,
+    normalize_cache_key: bool,
+    gzip_espeak: bool,
+    spoken_errors: Option<SpokenErrors>,
+    hot_keys: Option<hot_keys::HotKeys>,
+    language_detector: Option<language::Detector>,
+    sentence_cache: Option<sentence_cache::SentenceCache>,
+    voice_aliases: voice_aliases::VoiceAliases,
 
     polly: polly::State,
     gtts: tokio::sync::RwLock<gtts::State>,
     gcloud: tokio::sync::RwLock<gcloud::State>,
+    elevenlabs: elevenlabs::State,
 }
 
 static STATE: OnceLock<State> = OnceLock::new();
 
+impl State {
+    /// The voice requested for the mode, falling back to its `{MODE}_DEFAULT_VOICE`.
+    fn requested_voice(
+        &self,
+        mode: TTSMode,
+        voice: Option<FixedString<u8>>,
+    ) -> ResponseResult<FixedString<u8>> {
+        voice
+            .or_else(|| self.default_voice.get(mode).clone())
+            .ok_or_else(|| {
+                Error::UnknownVoice(
+                    format!("No voice was given, and {mode} has no default voice").into_boxed_str(),
+                )
+            })
+    }
+}
+
+/// Checks the `Authorization` header against the auth keys, returning the label of the matched key.
+///
+/// If no auth keys are set, every request is allowed and `None` is returned.
+fn check_auth<'a>(
+    state: &'a State,
+    headers: &axum::http::HeaderMap,
+) -> ResponseResult<Option<&'a str>> {
+    if state.auth_keys.is_empty() {
+        return Ok(None);
+    }
+
+    let auth_header = headers
+        .get("Authorization")
+        .map(HeaderValue::to_str)
+        .transpose()?;
+
+    match state
+        .auth_keys
+        .iter()
+        .find(|auth_key| Some(auth_key.key.as_str()) == auth_header)
+    {
+        Some(auth_key) => Ok(Some(auth_key.label.as_str())),
+        None => Err(Error::Unauthorized),
+    }
+}
+
+/// Reads the comma separated `TRANSLATION_SKIP_LANGS`, uppercased to match `DeepL`'s language codes.
+fn read_translation_skip_langs() -> Vec<String> {
+    let Ok(langs) = std::env::var("TRANSLATION_SKIP_LANGS") else {
+        return Vec::new();
+    };
+
+    langs
+        .split(',')
+        .map(|lang| lang.trim().to_uppercase())
+        .filter(|lang| !lang.is_empty())
+        .collect()
+}
+
+/// Reads the auth key from the file at `AUTH_KEY_FILE`, falling back to `AUTH_KEY`,
+/// along with any `label:key` pairs in the comma separated `AUTH_KEYS`.
+fn read_auth_keys() -> Result<Vec<AuthKey>> {
+    let mut auth_keys = Vec::new();
+    let auth_key = match std::env::var("AUTH_KEY_FILE") {
+        Ok(path) => Some(std::fs::read_to_string(path)?.trim().to_owned()),
+        Err(_) => std::env::var("AUTH_KEY").ok(),
+    };
+
+    if let Some(auth_key) = auth_key {
+        auth_keys.push(AuthKey {
+            key: str_to_fixedstring(auth_key),
+            label: FixedString::from_static_trunc("default"),
+        });
+    }
+
+    if let Ok(keys) = std::env::var("AUTH_KEYS") {
+        for entry in keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((label, key)) = entry.split_once(':') else {
+                anyhow::bail!("AUTH_KEYS entries must be in the format label:key");
+            };
+
+            auth_keys.push(AuthKey {
+                key: str_to_fixedstring(key.to_owned()),
+                label: str_to_fixedstring(label.to_owned()),
+            });
+        }
+    }
+
+    Ok(auth_keys)
+}
+
+/// Reads the comma separated `Name:Value` pairs from `{PREFIX}_EXTRA_HEADERS`, which are
+/// sent with every request to that backend, such as for a proxy requiring authentication.
+fn read_extra_headers(prefix: &str) -> Result<reqwest::header::HeaderMap> {
+    let mut extra_headers = reqwest::header::HeaderMap::new();
+    let Ok(raw) = std::env::var(format!("{prefix}_EXTRA_HEADERS")) else {
+        return Ok(extra_headers);
+    };
+
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((name, value)) = entry.split_once(':') else {
+            anyhow::bail!("{prefix}_EXTRA_HEADERS entries must be in the format Name:Value");
+        };
+
+        extra_headers.insert(
+            reqwest::header::HeaderName::from_str(name.trim())?,
+            HeaderValue::from_str(value.trim())?,
+        );
+    }
+
+    Ok(extra_headers)
+}
+
+/// The extra headers for a reqwest client, with a `User-Agent` of `USER_AGENT` unless
+/// one is set by `{PREFIX}_EXTRA_HEADERS`, so backends can identify the service.
+fn read_client_headers(prefix: &str) -> Result<reqwest::header::HeaderMap> {
+    let mut headers = read_extra_headers(prefix)?;
+    if !headers.contains_key(reqwest::header::USER_AGENT) {
+        let user_agent = match std::env::var("USER_AGENT") {
+            Ok(user_agent) => HeaderValue::from_str(&user_agent)?,
+            Err(_) => HeaderValue::from_static(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            )),
+        };
+
+        headers.insert(reqwest::header::USER_AGENT, user_agent);
+    }
+
+    Ok(headers)
+}
+
+/// Reads `{prefix}_{key}`, falling back to `HTTP_{key}` if unset.
+fn parse_client_env<T: FromStr>(prefix: &str, key: &str) -> Option<T> {
+    parse_env(&format!("{prefix}_{key}")).or_else(|| parse_env(&format!("HTTP_{key}")))
+}
+
+fn build_client(prefix: &str) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().default_headers(read_client_headers(prefix)?);
+    if let Some(max_idle) = parse_client_env(prefix, "POOL_MAX_IDLE_PER_HOST") {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+
+    if let Some(idle_timeout) = parse_client_env(prefix, "POOL_IDLE_TIMEOUT_SECS") {
+        builder = builder.pool_idle_timeout(Duration::from_secs(idle_timeout));
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Synthesizes `SAMPLE_TEXT` with one of the mode's voices, returning the length of the audio.
+async fn selftest_mode(state: &State, mode: TTSMode) -> Result<usize> {
+    let voices = mode.voices(state).await?;
+    let Some(voice) = voices.iter().find(|v| *v == "en").or(voices.first()) else {
+        anyhow::bail!("{mode} has no voices");
+    };
+
+    let (audio, _) = mode
+        .synthesize(
+            state,
+            FixedString::from_static_trunc(SAMPLE_TEXT),
+            voice,
+            &SynthesisOptions::default(),
+            Arc::default(),
+        )
+        .await?;
+
+    if audio.len() < MIN_AUDIO_BYTES {
+        anyhow::bail!("{mode} returned only {} bytes of audio", audio.len());
+    }
+
+    Ok(audio.len())
+}
+
+/// Synthesizes a short phrase with each of the `STARTUP_SELFTEST_MODES`, failing if any
+/// cannot, so misconfiguration is caught at deploy time instead of on the first request.
+async fn run_selftest(state: &State) -> Result<()> {
+    let modes = match std::env::var("STARTUP_SELFTEST_MODES") {
+        Ok(mode_names) => mode_names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                TTSMode::from_name(name).ok_or_else(|| {
+                    anyhow::anyhow!("STARTUP_SELFTEST_MODES contains unknown mode {name}")
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Err(_) => TTSMode::configured(state).collect(),
+    };
+
+    let mut failed = Vec::new();
+    for mode in modes {
+        match selftest_mode(state, mode).await {
+            Ok(audio_len) => {
+                tracing::info!("Self-test of {mode} passed, with {audio_len} bytes of audio");
+            }
+            Err(err) => {
+                tracing::error!("Self-test of {mode} failed: {err:?}");
+                failed.push(mode);
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        anyhow::bail!("Startup self-test failed for {failed:?}");
+    }
+
+    Ok(())
+}
+
+/// The mode and voice used to speak user-facing errors, instead of returning JSON.
+struct SpokenErrors {
+    mode: TTSMode,
+    voice: FixedString<u8>,
+}
+
+/// Reads `SPOKEN_ERRORS_MODE` and `SPOKEN_ERRORS_VOICE`, if `SPOKEN_ERRORS` is enabled.
+fn read_spoken_errors() -> Result<Option<SpokenErrors>> {
+    if !parse_env("SPOKEN_ERRORS").unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let mode = match std::env::var("SPOKEN_ERRORS_MODE") {
+        Ok(mode_name) => TTSMode::from_name(&mode_name).ok_or_else(|| {
+            anyhow::anyhow!("SPOKEN_ERRORS_MODE is set to unknown mode {mode_name}")
+        })?,
+        Err(_) => TTSMode::gTTS,
+    };
+
+    let voice = match std::env::var("SPOKEN_ERRORS_VOICE") {
+        Ok(voice) => str_to_fixedstring(voice),
+        Err(_) if matches!(mode, TTSMode::gTTS) => FixedString::from_static_trunc("en"),
+        Err(_) => {
+            anyhow::bail!("SPOKEN_ERRORS_VOICE must be set when SPOKEN_ERRORS_MODE is not gTTS")
+        }
+    };
+
+    Ok(Some(SpokenErrors { mode, voice }))
+}
+
+/// Reads `{PREFIX}_DEFAULT_FORMAT` for each mode, used if a request has no `preferred_format`.
+fn read_default_formats() -> Result<PerMode<Option<FixedString<u8>>>> {
+    let default_formats = PerMode::from_fn(|mode| {
+        std::env::var(format!("{}_DEFAULT_FORMAT", mode.env_prefix()))
+            .ok()
+            .map(str_to_fixedstring)
+    });
+
+    for mode in TTSMode::ALL {
+        if let Some(format) = default_formats.get(mode) {
+            if !mode.check_format(format) {
+                anyhow::bail!(
+                    "{}_DEFAULT_FORMAT is set to {format}, which is not supported by {mode}",
+                    mode.env_prefix()
+                );
+            }
+        }
+    }
+
+    Ok(default_formats)
+}
+
+fn parse_env<T: FromStr>(key: &str) -> Option<T> {
+    std::env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
 fn str_to_fixedstring<LenT: ValidLength>(str: String) -> FixedString<LenT> {
     FixedString::try_from(str.into_boxed_str()).expect("string should be less than 256 chars long")
 }
 
+fn build_cache() -> ArcSwap<AudioCache> {
+    let max_cap = parse_env("CACHE_MAX_CAPACITY").unwrap_or(1000);
+    // Pre-sized to avoid repeatedly rehashing while the cache fills after a restart.
+    let initial_cap = parse_env("CACHE_INITIAL_CAPACITY")
+        .unwrap_or_else(|| usize::try_from(max_cap / 4).unwrap_or(usize::MAX));
+
+    let cache = Cache::builder()
+        .max_capacity(max_cap)
+        .initial_capacity(initial_cap)
+        .build();
+
+    tracing::info!(
+        "Initialised audio cache with max capacity: {max_cap}, initial capacity: {initial_cap}"
+    );
+    ArcSwap::from_pointee(AudioCache::new(cache, LargeAudioCache::from_env()))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let log_env = std::env::var("LOG_LEVEL");
@@ -463,57 +2343,90 @@ async fn main() -> Result<()> {
     let ip_block = match std::env::var("IPV6_BLOCK") {
         Ok(ip_block) if &ip_block == "DISABLE" => None,
         Ok(ip_block) => Some(ip_block.parse().expect("Invalid IPV6 Block!")),
-        _ => panic!("IPV6_BLOCK not set! Set to \"DISABLE\" to disable rate limit bypass"),
+        Err(_) => {
+            tracing::warn!("IPV6_BLOCK not set, gTTS rate limit bypass is disabled");
+            None
+        }
     };
 
-    let client = reqwest::Client::new();
-    let result = STATE.set(State {
-        reqwest: client.clone(),
-        gcloud: gcloud::State::new(client)?,
-        polly: polly::State::new(&aws_config::load_from_env().await),
-        gtts: tokio::sync::RwLock::new(gtts::get_random_ipv6(ip_block).await?),
+    gtts::load_voices()?;
+    postprocess::load()?;
 
-        cache: {
-            let max_cap = std::env::var("CACHE_MAX_CAPACITY")
+    let result = STATE.set(State {
+        reqwest: build_client("DEEPL")?,
+        gcloud: gcloud::State::new(build_client(TTSMode::gCloud.env_prefix())?)?,
+        elevenlabs: elevenlabs::State::new(build_client(TTSMode::ElevenLabs.env_prefix())?),
+        polly: polly::new_state(
+            &aws_config::load_from_env().await,
+            &read_extra_headers(TTSMode::Polly.env_prefix())?,
+        )?,
+        gtts: tokio::sync::RwLock::new(
+            gtts::get_random_ipv6(ip_block, read_client_headers(TTSMode::gTTS.env_prefix())?)
+                .await?,
+        ),
+
+        cache: build_cache(),
+        cache_min_text_length: parse_env("CACHE_MIN_TEXT_LENGTH").unwrap_or(0),
+        inflight: InflightMap::default(),
+        audio_budget: parse_env("MAX_INFLIGHT_AUDIO_BYTES").map(AudioBudget::new),
+        max_text_length: PerMode::from_env("MAX_TEXT_LENGTH"),
+        default_max_length: parse_env("DEFAULT_MAX_LENGTH_SECS"),
+        max_length_cap: parse_env("MAX_LENGTH_CAP_SECS"),
+        max_request_timeout_ms: parse_env("MAX_REQUEST_TIMEOUT_MS").unwrap_or(30_000),
+        default_format: read_default_formats()?,
+        default_voice: PerMode::from_fn(|mode| {
+            std::env::var(format!("{}_DEFAULT_VOICE", mode.env_prefix()))
                 .ok()
-                .and_then(|c| c.parse().ok())
-                .unwrap_or(1000);
-
-            let cache = Cache::builder().max_capacity(max_cap).build();
-
-            tracing::info!("Initialised audio cache with max capacity: {max_cap}");
-            ArcSwap::from_pointee(AudioCache {
-                inner: cache,
-                hits: AtomicU64::new(0),
-                misses: AtomicU64::new(0),
-            })
-        },
-
-        auth_key: std::env::var("AUTH_KEY").ok().map(str_to_fixedstring),
-        translation_key: std::env::var("DEEPL_KEY").ok().map(str_to_fixedstring),
+                .map(str_to_fixedstring)
+        }),
+        healthy: PerMode::from_fn(|_| AtomicBool::new(true)),
+        max_body_bytes: parse_env("MAX_BODY_BYTES").unwrap_or(64 * 1024),
+        normalize_text: parse_env("NORMALIZE_TEXT").unwrap_or(true),
+        normalize_cache_key: parse_env("NORMALIZE_CACHE_KEY").unwrap_or(false),
+        gzip_espeak: parse_env("ESPEAK_GZIP").unwrap_or(false),
+        spoken_errors: read_spoken_errors()?,
+        hot_keys: parse_env("HOT_KEYS_CAPACITY").map(hot_keys::HotKeys::new),
+        language_detector: language::Detector::from_env(),
+        sentence_cache: sentence_cache::SentenceCache::from_env(),
+        voice_aliases: voice_aliases::load()?,
+
+        auth_keys: read_auth_keys()?,
+        translation_keys: translation::Keys::from_env(),
+        translation_skip_langs: read_translation_skip_langs(),
     });
 
     if result.is_err() {
         unreachable!()
     }
 
-    let app = axum::Router::new()
+    espeak::load_voices().await;
+
+    if parse_env("STARTUP_SELFTEST").unwrap_or(false) {
+        run_selftest(STATE.get().unwrap()).await?;
+    }
+
+    let body_limit = DefaultBodyLimit::max(STATE.get().unwrap().max_body_bytes);
+    let mut app = axum::Router::new()
         .route("/tts", get(get_tts))
         .route("/voices", get(get_voices))
+        .route("/voices/reload", post(reload_voices))
+        .route("/voices/refresh", post(refresh_voices))
+        .route("/languages", get(get_languages))
+        .route("/config", get(get_config))
+        .route("/benchmark", post(benchmark))
         .route("/cache", get(get_cache_info))
-        .route("/cache", post(refresh_cache))
+        .route("/cache", post(refresh_cache).layer(body_limit))
         .route("/translation_languages", get(get_translation_languages))
-        .route(
-            "/modes",
-            get(|| async {
-                axum::Json([
-                    TTSMode::gTTS,
-                    TTSMode::Polly,
-                    TTSMode::eSpeak,
-                    TTSMode::gCloud,
-                ])
-            }),
-        );
+        .route("/translate", get(translate))
+        .route("/modes", get(get_modes));
+
+    if STATE.get().unwrap().hot_keys.is_some() {
+        app = app.route("/cache/hot", get(get_hot_keys));
+    }
+
+    if parse_env("EXPERIMENTAL_CACHE_PREFIX").unwrap_or(false) {
+        app = app.route("/cache/prefix", get(get_cached_prefix));
+    }
 
     let env_addr = std::env::var("BIND_ADDR");
     let bind_to = env_addr.as_deref().unwrap_or("0.0.0.0:3000");
@@ -521,8 +2434,7 @@ async fn main() -> Result<()> {
     tracing::info!("Binding to {bind_to}...");
 
     let listener = tokio::net::TcpListener::bind(bind_to).await?;
-    axum::serve(listener, app.into_make_service()).await?;
-    Ok(())
+    server::serve(listener, app).await
 }
 
 #[derive(Debug)]
@@ -530,15 +2442,95 @@ enum Error {
     Unauthorized,
     TranslationDisabled,
     UnknownVoice(Box<str>),
+    InvalidStyle(Box<str>),
+    InvalidSampleRate(Box<str>),
+    InvalidPronunciations(Box<str>),
+    InvalidVoiceClone(Box<str>),
     AudioTooLong,
+    TextTooLong(usize),
     InvalidSpeakingRate(f32),
+    InvalidTargetLoudness(f32),
+    LanguageMismatch(Box<str>),
+    NotCached,
+    Timeout,
+    QuotaExceeded,
+    RateLimited,
+    Overloaded,
 
     Unknown(anyhow::Error),
 }
 
+/// Returned by the backends when the upstream service reports that its quota has been used up.
+#[derive(Debug)]
+pub struct QuotaExceeded;
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Backend quota exceeded")
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Returned by the backends when the upstream service is throttling requests, which
+/// unlike `QuotaExceeded` should succeed if retried shortly.
+#[derive(Debug)]
+pub struct RateLimited;
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Backend rate limit exceeded")
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// How long clients are told to wait before retrying a `RateLimited` request, in seconds.
+const RATE_LIMITED_RETRY_AFTER: &str = "1";
+
+/// Returned by the backends when the text is too long for them, with the maximum length.
+#[derive(Debug)]
+pub struct TextTooLong(pub usize);
+
+impl std::fmt::Display for TextTooLong {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Text is longer than {} characters", self.0)
+    }
+}
+
+impl std::error::Error for TextTooLong {}
+
+/// Returned by the backends with the id the upstream service gave the failed request, for support tickets.
+#[derive(Debug)]
+pub struct RequestFailed {
+    pub request_id: String,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for RequestFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (request id: {})", self.source, self.request_id)
+    }
+}
+
+impl std::error::Error for RequestFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
 impl<E: Into<anyhow::Error>> From<E> for Error {
     fn from(e: E) -> Self {
-        Self::Unknown(e.into())
+        let err = e.into();
+        if err.is::<QuotaExceeded>() {
+            Self::QuotaExceeded
+        } else if err.is::<RateLimited>() {
+            Self::RateLimited
+        } else if let Some(TextTooLong(max)) = err.downcast_ref() {
+            Self::TextTooLong(*max)
+        } else {
+            Self::Unknown(err)
+        }
     }
 }
 
@@ -546,44 +2538,114 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::InvalidSpeakingRate(rate) => write!(f, "Invalid speaking rate: {rate}"),
+            Self::InvalidTargetLoudness(lufs) => write!(
+                f,
+                "Invalid target loudness: {lufs}, must be between {} and {} LUFS",
+                ffmpeg::LOUDNESS_RANGE.start(),
+                ffmpeg::LOUDNESS_RANGE.end()
+            ),
             Self::AudioTooLong => f.write_str("Max length exceeded!"),
-            Self::UnknownVoice(msg) => f.write_str(msg),
+            Self::NotCached => f.write_str("The audio has not been cached"),
+            Self::Timeout => f.write_str("The request took longer than its X-Timeout-Ms"),
+            Self::TextTooLong(max) => write!(f, "Text is longer than {max} characters"),
+            Self::UnknownVoice(msg)
+            | Self::InvalidStyle(msg)
+            | Self::InvalidSampleRate(msg)
+            | Self::InvalidPronunciations(msg)
+            | Self::InvalidVoiceClone(msg)
+            | Self::LanguageMismatch(msg) => f.write_str(msg),
             Self::Unauthorized => write!(f, "Unauthorized request"),
             Self::TranslationDisabled => {
-                write!(f, "Translation requested but no key has been provided")
+                write!(
+                    f,
+                    "Translation or language detection requested but no key has been provided"
+                )
+            }
+            Self::QuotaExceeded => {
+                f.write_str("The TTS service has hit its usage limit, try again later")
+            }
+            Self::RateLimited => {
+                f.write_str("The TTS service is being rate limited, try again shortly")
+            }
+            Self::Overloaded => {
+                f.write_str("Too much audio is being generated at once, try again later")
             }
             Self::Unknown(e) => write!(f, "Unknown error: {e}"),
         }
     }
 }
 
+impl Error {
+    const fn code(&self) -> u8 {
+        match self {
+            Self::RateLimited => 17,
+            Self::Timeout => 16,
+            Self::InvalidVoiceClone(_) => 15,
+            Self::NotCached => 14,
+            Self::LanguageMismatch(_) => 13,
+            Self::InvalidTargetLoudness(_) => 12,
+            Self::Overloaded => 11,
+            Self::InvalidPronunciations(_) => 10,
+            Self::TextTooLong(_) => 9,
+            Self::InvalidSampleRate(_) => 8,
+            Self::QuotaExceeded => 7,
+            Self::InvalidStyle(_) => 6,
+            Self::TranslationDisabled => 5,
+            Self::Unauthorized => 4,
+            Self::InvalidSpeakingRate(_) => 3,
+            Self::AudioTooLong => 2,
+            Self::UnknownVoice(_) => 1,
+            Self::Unknown(_) => 0,
+        }
+    }
+}
+
 impl axum::response::IntoResponse for Error {
     fn into_response(self) -> Response {
         if let Error::Unknown(inner) = &self {
             tracing::error!("{inner:?}");
-        };
+        }
 
-        let json_err = serde_json::json!({
+        let mut json_err = serde_json::json!({
             "display": self.to_string(),
-            "code": match self {
-                Self::TranslationDisabled => 5,
-                Self::Unauthorized => 4,
-                Self::InvalidSpeakingRate(_) => 3,
-                Self::AudioTooLong => 2,
-                Self::UnknownVoice(_) => 1,
-                Self::Unknown(_) => 0_u8,
-            },
+            "code": self.code(),
         });
 
-        let status = match self {
-            Self::AudioTooLong | Self::InvalidSpeakingRate(_) | Self::TranslationDisabled => {
-                axum::http::StatusCode::BAD_REQUEST
+        if let Error::Unknown(inner) = &self {
+            if let Some(RequestFailed { request_id, .. }) = inner.downcast_ref() {
+                json_err["request_id"] = serde_json::Value::from(request_id.as_str());
             }
+        }
+
+        let retry_after = matches!(self, Self::RateLimited);
+        let status = match self {
+            Self::AudioTooLong
+            | Self::TextTooLong(_)
+            | Self::UnknownVoice(_)
+            | Self::InvalidStyle(_)
+            | Self::InvalidSampleRate(_)
+            | Self::InvalidPronunciations(_)
+            | Self::InvalidVoiceClone(_)
+            | Self::InvalidSpeakingRate(_)
+            | Self::InvalidTargetLoudness(_)
+            | Self::LanguageMismatch(_)
+            | Self::TranslationDisabled => axum::http::StatusCode::BAD_REQUEST,
+            Self::QuotaExceeded | Self::RateLimited => axum::http::StatusCode::TOO_MANY_REQUESTS,
+            Self::Overloaded => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            Self::NotCached => axum::http::StatusCode::NOT_FOUND,
+            Self::Timeout => axum::http::StatusCode::GATEWAY_TIMEOUT,
             Self::Unknown(_) => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Self::UnknownVoice(_) => axum::http::StatusCode::BAD_REQUEST,
             Self::Unauthorized => axum::http::StatusCode::FORBIDDEN,
         };
 
-        (status, axum::Json(json_err)).into_response()
+        let mut response = (status, axum::Json(json_err)).into_response();
+        if retry_after {
+            response.headers_mut().insert(
+                axum::http::header::RETRY_AFTER,
+                HeaderValue::from_static(RATE_LIMITED_RETRY_AFTER),
+            );
+        }
+
+        response
     }
 }
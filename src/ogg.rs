@@ -0,0 +1,194 @@
+//! Helpers for checking Ogg streams, see <https://www.rfc-editor.org/rfc/rfc3533>
+
+const HEADER_LEN: usize = 27;
+const BEGINNING_OF_STREAM: u8 = 0x02;
+
+#[derive(Clone, Copy)]
+pub enum Validation {
+    /// A single logical stream of well formed pages.
+    Valid,
+    /// Well formed pages, but of multiple streams one after another, which many players stop after the first of.
+    Chained,
+    Malformed(&'static str),
+}
+
+/// The CRC-32 used by Ogg pages, with polynomial `0x04c11db7` and no reflection.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x04c1_1db7
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn page_crc(page: &[u8]) -> u32 {
+    page.iter().enumerate().fold(0, |crc, (i, &byte)| {
+        // The checksum field itself is treated as zeroes.
+        let byte = if (22..26).contains(&i) { 0 } else { byte };
+        (crc << 8) ^ CRC_TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    })
+}
+
+/// Walks the pages of the Ogg stream, checking their structure and checksums.
+pub fn validate(audio: &[u8]) -> Validation {
+    let mut streams = 0_usize;
+    let mut offset = 0;
+    while offset < audio.len() {
+        let Some(header) = audio.get(offset..offset + HEADER_LEN) else {
+            return Validation::Malformed("truncated page header");
+        };
+
+        if &header[..4] != b"OggS" {
+            return Validation::Malformed("missing capture pattern");
+        }
+
+        let segment_count = usize::from(header[26]);
+        let Some(segment_table) =
+            audio.get(offset + HEADER_LEN..offset + HEADER_LEN + segment_count)
+        else {
+            return Validation::Malformed("truncated segment table");
+        };
+
+        let body_len: usize = segment_table.iter().copied().map(usize::from).sum();
+        let page_len = HEADER_LEN + segment_count + body_len;
+        let Some(page) = audio.get(offset..offset + page_len) else {
+            return Validation::Malformed("truncated page body");
+        };
+
+        let checksum = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+        if page_crc(page) != checksum {
+            return Validation::Malformed("page checksum mismatch");
+        }
+
+        if header[5] & BEGINNING_OF_STREAM != 0 {
+            streams += 1;
+        }
+
+        offset += page_len;
+    }
+
+    match streams {
+        0 => Validation::Malformed("no beginning of stream page"),
+        1 => Validation::Valid,
+        _ => Validation::Chained,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{page_crc, validate, Validation, BEGINNING_OF_STREAM};
+
+    const END_OF_STREAM: u8 = 0x04;
+
+    /// A page of a single segment, with a valid checksum.
+    fn page(serial: u32, sequence: u32, flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(flags);
+        page.extend_from_slice(&0_u64.to_le_bytes()); // Granule position
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]); // Checksum
+        page.push(1);
+        page.push(u8::try_from(body.len()).unwrap());
+        page.extend_from_slice(body);
+
+        let crc = page_crc(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn stream(serial: u32) -> Vec<u8> {
+        let mut stream = page(serial, 0, BEGINNING_OF_STREAM, b"OpusHead");
+        stream.extend(page(serial, 1, 0, b"OpusTags"));
+        stream.extend(page(serial, 2, END_OF_STREAM, b"audio"));
+        stream
+    }
+
+    #[test]
+    fn crc_matches_reference() {
+        // CRC-32/CKSUM's check value, without its final inversion.
+        assert_eq!(page_crc(b"123456789"), 0x765E_7680 ^ 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn validates_single_stream() {
+        assert!(matches!(validate(&stream(1)), Validation::Valid));
+    }
+
+    #[test]
+    fn detects_chained_streams() {
+        let mut audio = stream(1);
+        audio.extend(stream(2));
+
+        assert!(matches!(validate(&audio), Validation::Chained));
+    }
+
+    #[test]
+    fn rejects_corrupted_checksum() {
+        let mut audio = stream(1);
+        let last = audio.len() - 1;
+        audio[last] ^= 0xFF;
+
+        assert!(matches!(
+            validate(&audio),
+            Validation::Malformed("page checksum mismatch")
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_final_page() {
+        let audio = stream(1);
+        let last_page = audio.len() - page(1, 2, END_OF_STREAM, b"audio").len();
+
+        assert!(matches!(
+            validate(&audio[..audio.len() - 2]),
+            Validation::Malformed("truncated page body")
+        ));
+        assert!(matches!(
+            validate(&audio[..last_page + 27]),
+            Validation::Malformed("truncated segment table")
+        ));
+        assert!(matches!(
+            validate(&audio[..last_page + 10]),
+            Validation::Malformed("truncated page header")
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_capture_pattern() {
+        let mut audio = b"ID3\x04garbage".to_vec();
+        audio.extend(stream(1));
+
+        assert!(matches!(
+            validate(&audio),
+            Validation::Malformed("missing capture pattern")
+        ));
+        assert!(matches!(
+            validate(&[b"RIFF".as_slice(), &[0; 40]].concat()),
+            Validation::Malformed("missing capture pattern")
+        ));
+    }
+
+    #[test]
+    fn rejects_stream_without_beginning() {
+        let audio = page(1, 0, 0, b"audio");
+
+        assert!(matches!(
+            validate(&audio),
+            Validation::Malformed("no beginning of stream page")
+        ));
+    }
+}
@@ -1,11 +1,62 @@
-use aws_sdk_polly::types::{Engine, Gender, LanguageCode, OutputFormat, TextType, VoiceId};
+use std::{collections::BTreeSet, sync::Arc};
+
+use aformat::ToArrayString;
+use aws_sdk_polly::{
+    config::{
+        interceptors::BeforeTransmitInterceptorContextMut, ConfigBag, Intercept, RuntimeComponents,
+    },
+    error::{BoxError, ProvideErrorMetadata, SdkError},
+    operation::{describe_voices::DescribeVoicesError, RequestId},
+    types::{Engine, Gender, LanguageCode, OutputFormat, TextType, VoiceId},
+};
 use serde::ser::SerializeStruct;
 use small_fixed_array::FixedString;
 
-use crate::Result;
+use crate::{ffmpeg, text, voice_cache::VoiceCache, wav, RateLimited, RequestFailed, Result};
 
 pub type State = aws_sdk_polly::Client;
 
+/// Adds the `POLLY_EXTRA_HEADERS` to every request made by the SDK.
+#[derive(Debug)]
+struct ExtraHeaders(Vec<(String, String)>);
+
+impl Intercept for ExtraHeaders {
+    fn name(&self) -> &'static str {
+        "ExtraHeaders"
+    }
+
+    fn modify_before_transmit(
+        &self,
+        context: &mut BeforeTransmitInterceptorContextMut<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let headers = context.request_mut().headers_mut();
+        for (name, value) in &self.0 {
+            headers.insert(name.clone(), value.clone());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn new_state(
+    sdk_config: &aws_config::SdkConfig,
+    extra_headers: &reqwest::header::HeaderMap,
+) -> Result<State> {
+    let mut config = aws_sdk_polly::config::Builder::from(sdk_config);
+    if !extra_headers.is_empty() {
+        let extra_headers = extra_headers
+            .iter()
+            .map(|(name, value)| Ok((name.as_str().to_owned(), value.to_str()?.to_owned())))
+            .collect::<Result<_>>()?;
+
+        config = config.interceptor(ExtraHeaders(extra_headers));
+    }
+
+    Ok(aws_sdk_polly::Client::from_conf(config.build()))
+}
+
 pub struct VoiceLocal {
     pub additional_language_codes: Option<Vec<LanguageCode>>,
     pub supported_engines: Option<Vec<Engine>>,
@@ -59,51 +110,221 @@ impl serde::Serialize for VoiceLocal {
     }
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum Style {
+    News,
+    Conversational,
+}
+
+impl Style {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "news" => Some(Self::News),
+            "conversational" => Some(Self::Conversational),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::News => "news",
+            Self::Conversational => "conversational",
+        }
+    }
+
+    /// The neural voices which support this speaking style, from the Polly documentation.
+    fn supported_voices(self) -> &'static [&'static str] {
+        match self {
+            Self::News => &["Matthew", "Joanna", "Lupe", "Amy"],
+            Self::Conversational => &["Matthew", "Joanna"],
+        }
+    }
+}
+
+/// The sample rate Polly uses for PCM if one is not requested.
+const DEFAULT_PCM_SAMPLE_RATE: u32 = 16000;
+
+fn output_format(preferred_format: Option<&str>) -> OutputFormat {
+    preferred_format
+        .and_then(|pf| match pf.to_lowercase().as_str() {
+            "mp3" => Some(OutputFormat::Mp3),
+            "pcm" | "wav" => Some(OutputFormat::Pcm),
+            _ => None,
+        })
+        .unwrap_or(OutputFormat::OggVorbis)
+}
+
+/// The content type of audio in the given format, for when Polly does not return one.
+fn format_content_type(format: &OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Mp3 => "audio/mpeg",
+        OutputFormat::Pcm => "audio/pcm",
+        OutputFormat::Json => "application/x-json-stream",
+        _ => "audio/ogg",
+    }
+}
+
+pub const FORMATS: &[&str] = &["mp3", "pcm", "wav", "ogg_vorbis"];
+
+pub fn check_format(format: &str) -> bool {
+    FORMATS.iter().any(|f| f.eq_ignore_ascii_case(format))
+}
+
+/// Polly only accepts certain sample rates for each output format.
+pub fn check_sample_rate(preferred_format: Option<&str>, sample_rate: u32) -> bool {
+    let valid_rates: &[u32] = match output_format(preferred_format) {
+        OutputFormat::Pcm => &[8000, 16000],
+        _ => &[8000, 16000, 22050, 24000],
+    };
+
+    valid_rates.contains(&sample_rate)
+}
+
+/// Polly bills at most 3000 characters per request, rejecting anything longer.
+const MAX_CHUNK_CHARACTERS: usize = 3000;
+
+fn build_ssml(
+    text: &str,
+    speaking_rate: Option<u16>,
+    style: Option<Style>,
+    digits_mode: bool,
+) -> String {
+    let text = text::escape_xml(text);
+    let mut text = if digits_mode {
+        text::ssml_digits(&text)
+    } else {
+        text.into_owned()
+    };
+
+    if let Some(speaking_rate) = speaking_rate {
+        text = format!("<prosody rate=\"{speaking_rate}%\">{text}</prosody>");
+    }
+
+    if let Some(style) = style {
+        text = format!(
+            "<amazon:domain name=\"{}\">{text}</amazon:domain>",
+            style.as_str()
+        );
+    }
+
+    format!("<speak>{text}</speak>")
+}
+
+#[expect(clippy::too_many_arguments)]
 pub async fn get_tts(
     state: &State,
     text: FixedString,
     voice: &str,
-    speaking_rate: Option<u8>,
+    speaking_rate: Option<u16>,
     preferred_format: Option<&str>,
+    style: Option<Style>,
+    sample_rate: Option<u32>,
+    digits_mode: bool,
 ) -> Result<(bytes::Bytes, Option<reqwest::header::HeaderValue>)> {
-    let text = if let Some(speaking_rate) = speaking_rate {
-        format!("<speak><prosody rate=\"{speaking_rate}%\">{text}</prosody></speak>")
+    let is_ssml = speaking_rate.is_some() || style.is_some() || digits_mode;
+    let language = voice_languages(state, voice).await?.into_iter().next();
+    // Escaping and `say-as` tags are measured with the text, as they can make the SSML far longer,
+    // which keeps it well under Polly's limit of 6000 characters of SSML including the tags.
+    let chunks = text::split_chunks(&text, language.as_deref(), MAX_CHUNK_CHARACTERS, |s| {
+        if !is_ssml {
+            s.chars().count()
+        } else if digits_mode {
+            text::ssml_digits(&text::escape_xml(s)).chars().count()
+        } else {
+            text::escape_xml(s).chars().count()
+        }
+    });
+
+    // Chained Ogg streams are valid, but poorly supported by players, so when ffmpeg
+    // is available long text is requested as PCM and encoded to Ogg once stitched together.
+    let output_format = output_format(preferred_format);
+    let stitch_ogg =
+        chunks.len() > 1 && output_format == OutputFormat::OggVorbis && ffmpeg::is_enabled();
+
+    let (request_format, request_sample_rate) = if stitch_ogg {
+        (OutputFormat::Pcm, None)
     } else {
-        text.into_string()
+        (output_format, sample_rate)
     };
 
-    let resp = state
-        .synthesize_speech()
-        .set_text_type(Some(if speaking_rate.is_some() {
-            TextType::Ssml
-        } else {
-            TextType::Text
-        }))
-        .set_output_format(
-            preferred_format
-                .and_then(|pf| match pf.to_lowercase().as_str() {
-                    "mp3" => Some(OutputFormat::Mp3),
-                    "pcm" => Some(OutputFormat::Pcm),
-                    _ => None,
-                })
-                .or(Some(OutputFormat::OggVorbis)),
-        )
-        .set_engine(Some(Engine::Standard))
-        .set_voice_id(Some(voice.into()))
-        .set_text(Some(text))
-        .send()
-        .await?;
-
-    Ok((
-        resp.audio_stream.collect().await?.into_bytes(),
-        resp.content_type
-            .map(TryInto::try_into)
-            .and_then(Result::ok),
-    ))
-}
-
-static VOICES: tokio::sync::OnceCell<Vec<VoiceLocal>> = tokio::sync::OnceCell::const_new();
-async fn _get_voices(state: &State) -> Result<Vec<VoiceLocal>> {
+    let mut audio = Vec::new();
+    let mut content_type = None;
+    for chunk in chunks {
+        let resp = state
+            .synthesize_speech()
+            .set_text_type(Some(if is_ssml {
+                TextType::Ssml
+            } else {
+                TextType::Text
+            }))
+            .set_output_format(Some(request_format.clone()))
+            .set_sample_rate(request_sample_rate.map(|r| r.to_string()))
+            .set_engine(Some(if style.is_some() {
+                Engine::Neural
+            } else {
+                Engine::Standard
+            }))
+            .set_voice_id(Some(voice.into()))
+            .set_text(Some(if is_ssml {
+                build_ssml(chunk, speaking_rate, style, digits_mode)
+            } else {
+                chunk.to_owned()
+            }))
+            .send()
+            .await
+            .map_err(|err| {
+                if err.code() == Some("ThrottlingException") {
+                    return anyhow::Error::from(RateLimited);
+                }
+
+                // AWS support needs the request id to look into failed requests.
+                match err.request_id().map(str::to_owned) {
+                    Some(request_id) => anyhow::Error::from(RequestFailed {
+                        request_id,
+                        source: err.into(),
+                    }),
+                    None => anyhow::Error::from(err),
+                }
+            })?;
+
+        audio.extend_from_slice(&resp.audio_stream.collect().await?.into_bytes());
+        content_type = resp.content_type;
+    }
+
+    if stitch_ogg {
+        let pcm = wav::from_pcm(&audio, DEFAULT_PCM_SAMPLE_RATE, 1, 16)?;
+        let sample_rate = sample_rate.map(ToArrayString::to_arraystring);
+        let args: &[&str] = match &sample_rate {
+            Some(sample_rate) => &["-ar", sample_rate],
+            None => &[],
+        };
+
+        audio = ffmpeg::run(&pcm, args, ffmpeg::Format::OggVorbis)
+            .await?
+            .into();
+        content_type = Some(String::from("audio/ogg"));
+    }
+
+    if preferred_format.is_some_and(|pf| pf.eq_ignore_ascii_case("wav")) {
+        // Polly's PCM is headerless signed 16-bit mono, which most players cannot handle.
+        let sample_rate = sample_rate.unwrap_or(DEFAULT_PCM_SAMPLE_RATE);
+        return Ok((
+            bytes::Bytes::from(wav::from_pcm(&audio, sample_rate, 1, 16)?),
+            Some(reqwest::header::HeaderValue::from_static("audio/wav")),
+        ));
+    }
+
+    let content_type = content_type
+        .and_then(|content_type| content_type.try_into().ok())
+        .unwrap_or_else(|| {
+            reqwest::header::HeaderValue::from_static(format_content_type(&request_format))
+        });
+
+    Ok((bytes::Bytes::from(audio), Some(content_type)))
+}
+
+static VOICES: VoiceCache<Vec<VoiceLocal>> = VoiceCache::new();
+async fn fetch_voices(state: &State) -> Result<Vec<VoiceLocal>> {
     let mut voices = Vec::new();
     let mut next_token = None;
 
@@ -118,7 +339,7 @@ async fn _get_voices(state: &State) -> Result<Vec<VoiceLocal>> {
             voices.extend(v.into_iter().map(VoiceLocal::from).filter(|v| {
                 v.supported_engines
                     .as_ref()
-                    .map_or(false, |engines| engines.contains(&Engine::Standard))
+                    .is_some_and(|engines| engines.contains(&Engine::Standard))
             }));
         }
         if resp.next_token.is_none() {
@@ -129,27 +350,86 @@ async fn _get_voices(state: &State) -> Result<Vec<VoiceLocal>> {
     }
 }
 
+/// If fetching the voices failed to connect, timed out, or was rejected with a 429 or 5xx.
+pub fn is_transient_error(err: &anyhow::Error) -> bool {
+    let Some(err) = err.downcast_ref::<SdkError<DescribeVoicesError>>() else {
+        return false;
+    };
+
+    matches!(
+        err,
+        SdkError::DispatchFailure(_) | SdkError::TimeoutError(_)
+    ) || err
+        .raw_response()
+        .is_some_and(|resp| resp.status().is_server_error() || resp.status().as_u16() == 429)
+}
+
 pub async fn check_voice(state: &State, voice: &str) -> Result<bool> {
-    VOICES
-        .get_or_try_init(|| _get_voices(state))
+    get_raw_voices(state)
         .await
         .map(|voices| voices.iter().any(|s| s.id == Some(voice.into())))
 }
 
+/// The language codes the voice can speak, such as `en-US`.
+pub async fn voice_languages(state: &State, voice: &str) -> Result<Vec<String>> {
+    let voices = get_raw_voices(state).await?;
+    let Some(voice) = voices.iter().find(|v| v.id == Some(voice.into())) else {
+        return Ok(Vec::new());
+    };
+
+    Ok(voice
+        .language_code
+        .iter()
+        .chain(voice.additional_language_codes.iter().flatten())
+        .map(|language| String::from(language.as_str()))
+        .collect())
+}
+
+pub async fn check_style(state: &State, voice: &str, style: Style) -> Result<bool> {
+    if !style.supported_voices().contains(&voice) {
+        return Ok(false);
+    }
+
+    get_raw_voices(state).await.map(|voices| {
+        voices.iter().any(|v| {
+            v.id == Some(voice.into())
+                && v.supported_engines
+                    .as_ref()
+                    .is_some_and(|engines| engines.contains(&Engine::Neural))
+        })
+    })
+}
+
 pub async fn get_voices(state: &State) -> Result<Vec<String>> {
-    VOICES
-        .get_or_try_init(|| _get_voices(state))
-        .await
-        .map(|voices| {
-            voices
+    get_raw_voices(state).await.map(|voices| {
+        voices
+            .iter()
+            .filter_map(|v| v.id.as_ref())
+            .map(VoiceId::as_str)
+            .map(String::from)
+            .collect()
+    })
+}
+
+pub async fn get_languages(state: &State) -> Result<Vec<String>> {
+    let voices = get_raw_voices(state).await?;
+    let languages: BTreeSet<&str> = voices
+        .iter()
+        .flat_map(|v| {
+            v.language_code
                 .iter()
-                .filter_map(|v| v.id.as_ref())
-                .map(VoiceId::as_str)
-                .map(String::from)
-                .collect()
+                .chain(v.additional_language_codes.iter().flatten())
         })
+        .map(LanguageCode::as_str)
+        .collect();
+
+    Ok(languages.into_iter().map(String::from).collect())
+}
+
+pub async fn get_raw_voices(state: &State) -> Result<Arc<Vec<VoiceLocal>>> {
+    VOICES.get(|| fetch_voices(state)).await
 }
 
-pub async fn get_raw_voices(state: &State) -> Result<&'static Vec<VoiceLocal>> {
-    VOICES.get_or_try_init(|| _get_voices(state)).await
+pub async fn refresh_voices(state: &State) -> Result<()> {
+    VOICES.refresh(|| fetch_voices(state)).await
 }
@@ -0,0 +1,87 @@
+use std::{sync::OnceLock, time::Duration};
+
+use reqwest::header::HeaderValue;
+
+use crate::{parse_env, subprocess, Result};
+
+struct Config {
+    program: String,
+    args: Vec<String>,
+    content_type: Option<HeaderValue>,
+    timeout: Duration,
+}
+
+static CONFIG: OnceLock<Option<Config>> = OnceLock::new();
+
+impl Config {
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(command) = std::env::var("AUDIO_POSTPROCESS_CMD") else {
+            return Ok(None);
+        };
+
+        let mut parts = command.split_whitespace().map(String::from);
+        let Some(program) = parts.next() else {
+            return Ok(None);
+        };
+
+        let content_type = match std::env::var("AUDIO_POSTPROCESS_CONTENT_TYPE") {
+            Ok(content_type) => match HeaderValue::from_str(&content_type) {
+                Ok(content_type) => Some(content_type),
+                Err(_) => anyhow::bail!("Invalid AUDIO_POSTPROCESS_CONTENT_TYPE: {content_type}"),
+            },
+            Err(_) => None,
+        };
+
+        Ok(Some(Self {
+            program,
+            args: parts.collect(),
+            content_type,
+            timeout: Duration::from_millis(
+                parse_env("AUDIO_POSTPROCESS_TIMEOUT_MS").unwrap_or(10_000),
+            ),
+        }))
+    }
+}
+
+/// Loads the `AUDIO_POSTPROCESS_*` config, failing if it is invalid, so must be called at startup.
+pub fn load() -> Result<()> {
+    if CONFIG.set(Config::from_env()?).is_err() {
+        anyhow::bail!("Audio post-processing config has already been loaded");
+    }
+
+    Ok(())
+}
+
+fn get_config() -> Option<&'static Config> {
+    CONFIG.get()?.as_ref()
+}
+
+/// If `AUDIO_POSTPROCESS_CMD` has been set.
+pub fn is_enabled() -> bool {
+    get_config().is_some()
+}
+
+/// Pipes the audio through `AUDIO_POSTPROCESS_CMD`, returning it unchanged if unset.
+pub async fn run(
+    audio: bytes::Bytes,
+    content_type: Option<HeaderValue>,
+) -> Result<(bytes::Bytes, Option<HeaderValue>)> {
+    let Some(config) = get_config() else {
+        return Ok((audio, content_type));
+    };
+
+    let mut command = tokio::process::Command::new(&config.program);
+    command.args(&config.args);
+
+    let Ok(processed) =
+        tokio::time::timeout(config.timeout, subprocess::pipe(command, &audio)).await
+    else {
+        anyhow::bail!(
+            "Audio post-processing took longer than {} millis",
+            config.timeout.as_millis()
+        );
+    };
+
+    let content_type = config.content_type.clone().or(content_type);
+    Ok((bytes::Bytes::from(processed?), content_type))
+}
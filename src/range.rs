@@ -0,0 +1,44 @@
+use std::ops::Range;
+
+use axum::http::header::HeaderValue;
+
+pub enum ByteRange {
+    Full,
+    Partial(Range<usize>),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a body of `len` bytes.
+///
+/// Only a single `bytes` range is supported, anything else is ignored and the full body is served.
+pub fn parse(header: Option<&HeaderValue>, len: usize) -> ByteRange {
+    let Some(spec) = header
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+        // `bytes=start-end`, with the end being inclusive.
+        (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(len),
+        // `bytes=start-`
+        (Ok(start), Err(_)) if end.is_empty() => start..len,
+        // `bytes=-suffix_len`
+        (Err(_), Ok(suffix_len)) if start.is_empty() && suffix_len != 0 => {
+            len.saturating_sub(suffix_len)..len
+        }
+        _ => return ByteRange::Full,
+    };
+
+    if range.start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range)
+    }
+}
@@ -0,0 +1,123 @@
+//! Caches audio per sentence, so text made of already spoken sentences can be stitched
+//! together from the cache instead of synthesized, see `SENTENCE_CACHE_CAPACITY`.
+
+use std::sync::{atomic::AtomicBool, Arc};
+
+use aformat::ToArrayString;
+use axum::http::HeaderValue;
+use bytes::Bytes;
+use mini_moka::sync::Cache;
+use sha2::Digest as _;
+
+use crate::{
+    cache_key_text, ffmpeg, parse_env, sentences, str_to_fixedstring, wav, AudioCacheDigest,
+    Result, State, SynthesisOptions, TTSMode,
+};
+
+/// Sentences are stored as mono 16-bit PCM at this sample rate, so they can be joined.
+const SAMPLE_RATE: u32 = 24000;
+
+pub struct SentenceCache {
+    inner: Cache<AudioCacheDigest, Bytes>,
+}
+
+impl SentenceCache {
+    pub fn from_env() -> Option<Self> {
+        let capacity = parse_env("SENTENCE_CACHE_CAPACITY")?;
+        if !ffmpeg::is_enabled() {
+            tracing::warn!("SENTENCE_CACHE_CAPACITY is set, but FFMPEG_PATH is needed to join sentences, so it is disabled");
+            return None;
+        }
+
+        tracing::info!("Initialised sentence cache with max capacity: {capacity}");
+        Some(Self {
+            inner: Cache::new(capacity),
+        })
+    }
+
+    /// Synthesizes each sentence of the text that is not already cached, then joins the audio.
+    ///
+    /// Returns `None` if the text should be synthesized as normal, as it is a single sentence
+    /// or has been requested in a format which ffmpeg cannot produce.
+    #[expect(clippy::too_many_arguments)]
+    pub async fn synthesize(
+        &self,
+        state: &State,
+        mode: TTSMode,
+        text: &str,
+        voice: &str,
+        options: &SynthesisOptions<'_>,
+        key_suffix: &str,
+        hit_any_deadline: Arc<AtomicBool>,
+    ) -> Result<Option<(Bytes, Option<HeaderValue>)>> {
+        let languages = mode.voice_languages(state, voice).await?;
+        let language = languages.as_ref().and_then(|languages| languages.first());
+        let sentences: Vec<_> = sentences::split(text, language.map(String::as_str))
+            .map(str::trim)
+            .filter(|sentence| !sentence.is_empty())
+            .collect();
+
+        if sentences.len() < 2 || options.preferred_format.is_some() {
+            return Ok(None);
+        }
+
+        let Some(output_format) = mode.audio_format(None) else {
+            return Ok(None);
+        };
+
+        let mut pcm = Vec::new();
+        let mut cached_count = 0;
+        for sentence in &sentences {
+            let mut cache_key = cache_key_text(state, sentence).into_owned();
+            cache_key.push_str(key_suffix);
+            let cache_hash = sha2::Sha256::digest(&cache_key);
+
+            let sentence_pcm = if let Some(sentence_pcm) = self.inner.get(&cache_hash) {
+                cached_count += 1;
+                sentence_pcm
+            } else {
+                let text = str_to_fixedstring((*sentence).to_owned());
+                let (audio, _) = mode
+                    .synthesize(state, text, voice, options, hit_any_deadline.clone())
+                    .await?;
+
+                let sentence_pcm = decode(&audio).await?;
+                self.inner.insert(cache_hash, sentence_pcm.clone());
+                sentence_pcm
+            };
+
+            pcm.extend_from_slice(&sentence_pcm);
+        }
+
+        tracing::debug!(
+            "Joined {} sentences, {cached_count} of which were cached",
+            sentences.len()
+        );
+
+        let joined = wav::from_pcm(&pcm, SAMPLE_RATE, 1, 16)?;
+        let sample_rate = options.sample_rate.map(ToArrayString::to_arraystring);
+        let audio = match (&sample_rate, output_format) {
+            (None, ffmpeg::Format::Wav) => Bytes::from(joined),
+            (Some(sample_rate), _) => {
+                ffmpeg::run(&joined, &["-ar", sample_rate], output_format).await?
+            }
+            (None, _) => ffmpeg::run(&joined, &[], output_format).await?,
+        };
+
+        let content_type = HeaderValue::from_static(output_format.content_type());
+        Ok(Some((audio, Some(content_type))))
+    }
+}
+
+/// Decodes the audio to the PCM format sentences are stored as.
+async fn decode(audio: &[u8]) -> Result<Bytes> {
+    let sample_rate = SAMPLE_RATE.to_arraystring();
+    let args = ["-ac", "1", "-ar", &sample_rate, "-c:a", "pcm_s16le"];
+    let decoded = ffmpeg::run(audio, &args, ffmpeg::Format::Wav).await?;
+
+    let Some(parsed) = wav::parse(&decoded) else {
+        anyhow::bail!("ffmpeg returned malformed WAV audio");
+    };
+
+    Ok(decoded.slice(parsed.data_range()))
+}
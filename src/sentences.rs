@@ -0,0 +1,213 @@
+//! Splits text into sentences with the punctuation rules of its language, for chunking
+//! text between requests and for the sentence cache.
+
+use crate::language;
+
+/// How sentences end in the languages using a script.
+struct Rules {
+    /// Punctuation which always ends a sentence, as in scripts written without spaces.
+    terminators: &'static [char],
+    /// Punctuation which only ends a sentence when followed by whitespace, so decimals such
+    /// as `3.5` are not split, and a `.` also not after an abbreviation such as `Dr.`.
+    spaced_terminators: &'static [char],
+    /// Punctuation which opens a sentence, so ends the previous one even without whitespace.
+    openers: &'static [char],
+}
+
+/// Also used for text in unknown languages, so includes the full width CJK punctuation.
+const LATIN: Rules = Rules {
+    terminators: &['。', '！', '？'],
+    spaced_terminators: &['.', '!', '?'],
+    openers: &[],
+};
+
+const SPANISH: Rules = Rules {
+    openers: &['¿', '¡'],
+    ..LATIN
+};
+
+const CJK: Rules = Rules {
+    terminators: &['。', '！', '？', '．', '!', '?'],
+    ..LATIN
+};
+
+const INDIC: Rules = Rules {
+    terminators: &['।', '॥'],
+    ..LATIN
+};
+
+const ARABIC: Rules = Rules {
+    terminators: &['۔'],
+    spaced_terminators: &['.', '!', '?', '؟'],
+    openers: &[],
+};
+
+/// Greek uses `;` as its question mark.
+const GREEK: Rules = Rules {
+    spaced_terminators: &['.', '!', '?', ';', '\u{037E}'],
+    ..LATIN
+};
+
+const ARMENIAN: Rules = Rules {
+    spaced_terminators: &['։', '.', '!', '?', '՞'],
+    ..LATIN
+};
+
+const ETHIOPIC: Rules = Rules {
+    terminators: &['።', '፧', '!', '?'],
+    ..LATIN
+};
+
+const BURMESE: Rules = Rules {
+    terminators: &['။'],
+    ..LATIN
+};
+
+/// Words which are followed by a `.` without ending the sentence, compared case-insensitively.
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "vs", "e.g", "i.e",
+];
+
+/// Whether a `.` ends an abbreviation rather than the sentence, either as the end of a known
+/// abbreviation or an initial, or with the text carrying on in lowercase.
+fn is_abbreviation(before: &str, after: &str) -> bool {
+    let word = before
+        .rsplit(char::is_whitespace)
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(|c: char| !c.is_alphanumeric());
+
+    let mut word_chars = word.chars();
+    let is_initial =
+        word_chars.next().is_some_and(char::is_alphabetic) && word_chars.next().is_none();
+
+    is_initial
+        || ABBREVIATIONS.iter().any(|a| word.eq_ignore_ascii_case(a))
+        || after
+            .trim_start()
+            .chars()
+            .next()
+            .is_some_and(char::is_lowercase)
+}
+
+fn rules(language: Option<&str>) -> &'static Rules {
+    let Some(language) = language else {
+        return &LATIN;
+    };
+
+    match language::primary_subtag(language).as_str() {
+        "es" => &SPANISH,
+        "ja" | "zh" | "cmn" | "yue" => &CJK,
+        "hi" | "mr" | "ne" | "bn" | "pa" => &INDIC,
+        "ar" | "arb" | "fa" | "ur" | "ps" => &ARABIC,
+        "el" => &GREEK,
+        "hy" => &ARMENIAN,
+        "am" | "ti" => &ETHIOPIC,
+        "my" => &BURMESE,
+        _ => &LATIN,
+    }
+}
+
+/// Splits after sentence ending punctuation, keeping the punctuation and following whitespace.
+///
+/// `language` is a language code such as `en-US`, with unknown languages using Latin punctuation.
+pub fn split<'a>(text: &'a str, language: Option<&str>) -> impl Iterator<Item = &'a str> {
+    let rules = rules(language);
+    let mut chars = text.char_indices().peekable();
+    let mut start = 0;
+    let mut after_terminator = None;
+
+    std::iter::from_fn(move || {
+        while let Some((index, c)) = chars.next() {
+            let is_spaced_terminator = rules.spaced_terminators.contains(&c);
+            let is_end = c == '\n'
+                || rules.terminators.contains(&c)
+                || (c.is_whitespace()
+                    && after_terminator.is_some_and(|(terminator_index, terminator)| {
+                        terminator != '.'
+                            || !is_abbreviation(&text[start..terminator_index], &text[index..])
+                    }))
+                || (is_spaced_terminator
+                    && chars
+                        .peek()
+                        .is_some_and(|(_, next)| rules.openers.contains(next)));
+
+            after_terminator = is_spaced_terminator.then_some((index, c));
+            if is_end {
+                let end = index + c.len_utf8();
+                let sentence = &text[start..end];
+                start = end;
+                return Some(sentence);
+            }
+        }
+
+        let rest = (start < text.len()).then(|| &text[start..]);
+        start = text.len();
+        rest
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split;
+
+    fn sentences<'a>(text: &'a str, language: Option<&str>) -> Vec<&'a str> {
+        split(text, language).collect()
+    }
+
+    #[test]
+    fn splits_latin() {
+        assert_eq!(
+            sentences("Hello there. How are you? Great!", Some("en-US")),
+            ["Hello there. ", "How are you? ", "Great!"]
+        );
+        assert_eq!(sentences("One\nTwo", None), ["One\n", "Two"]);
+    }
+
+    #[test]
+    fn splits_cjk_without_spaces() {
+        assert_eq!(
+            sentences("今日は。元気ですか？はい！", Some("ja-JP")),
+            ["今日は。", "元気ですか？", "はい！"]
+        );
+        assert_eq!(
+            sentences("你好。你好吗？", Some("cmn-Hant-TW")),
+            ["你好。", "你好吗？"]
+        );
+
+        // Full width punctuation is split in unknown languages too.
+        assert_eq!(sentences("你好。你好吗？", None), ["你好。", "你好吗？"]);
+    }
+
+    #[test]
+    fn splits_spanish_openers() {
+        assert_eq!(
+            sentences("Hola.¿Qué tal? ¡Muy bien!", Some("es-ES")),
+            ["Hola.", "¿Qué tal? ", "¡Muy bien!"]
+        );
+    }
+
+    #[test]
+    fn keeps_decimals_and_abbreviations() {
+        assert_eq!(
+            sentences(
+                "It costs 3.50 dollars. Ask Dr. Smith, e.g. tomorrow.",
+                Some("en")
+            ),
+            ["It costs 3.50 dollars. ", "Ask Dr. Smith, e.g. tomorrow."]
+        );
+        assert_eq!(
+            sentences("J. R. R. Tolkien wrote it. Mr. Baggins did not.", None),
+            ["J. R. R. Tolkien wrote it. ", "Mr. Baggins did not."]
+        );
+        assert_eq!(sentences("Wait... what?", None), ["Wait... what?"]);
+    }
+
+    #[test]
+    fn keeps_all_text() {
+        let text = "  Leading space. ¿Sí?¡No!  Trailing 。";
+        for language in [None, Some("en"), Some("es"), Some("ja")] {
+            assert_eq!(sentences(text, language).concat(), text);
+        }
+    }
+}
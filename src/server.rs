@@ -0,0 +1,97 @@
+//! Serves the app with tuned HTTP/1 keep-alive and HTTP/2 settings, see `SERVER_*`.
+
+use std::time::Duration;
+
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+
+use crate::{parse_env, Result};
+
+/// How long to wait after failing to accept a connection, as hyper and axum do.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+struct Config {
+    http2_only: bool,
+    keep_alive: bool,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_keep_alive_timeout: Option<Duration>,
+    http2_max_concurrent_streams: Option<u32>,
+}
+
+impl Config {
+    fn from_env() -> Option<Self> {
+        let secs = |key| parse_env(key).map(Duration::from_secs);
+        let config = Self {
+            http2_only: parse_env("SERVER_HTTP2_ONLY").unwrap_or(false),
+            keep_alive: parse_env("SERVER_KEEP_ALIVE").unwrap_or(true),
+            http2_keep_alive_interval: secs("SERVER_HTTP2_KEEP_ALIVE_INTERVAL_SECS"),
+            http2_keep_alive_timeout: secs("SERVER_HTTP2_KEEP_ALIVE_TIMEOUT_SECS"),
+            http2_max_concurrent_streams: parse_env("SERVER_HTTP2_MAX_CONCURRENT_STREAMS"),
+        };
+
+        let is_default = !config.http2_only
+            && config.keep_alive
+            && config.http2_keep_alive_interval.is_none()
+            && config.http2_keep_alive_timeout.is_none()
+            && config.http2_max_concurrent_streams.is_none();
+
+        (!is_default).then_some(config)
+    }
+
+    fn builder(&self) -> auto::Builder<TokioExecutor> {
+        let mut builder = auto::Builder::new(TokioExecutor::new());
+        builder.http1().keep_alive(self.keep_alive);
+
+        let mut http2 = builder.http2();
+        http2
+            .timer(TokioTimer::new())
+            .keep_alive_interval(self.http2_keep_alive_interval);
+
+        if let Some(timeout) = self.http2_keep_alive_timeout {
+            http2.keep_alive_timeout(timeout);
+        }
+
+        if let Some(max_streams) = self.http2_max_concurrent_streams {
+            http2.max_concurrent_streams(max_streams);
+        }
+
+        if self.http2_only {
+            builder.http2_only()
+        } else {
+            builder
+        }
+    }
+}
+
+/// Serves the app, only replacing `axum::serve` if a `SERVER_*` setting has been changed.
+pub async fn serve(listener: tokio::net::TcpListener, app: axum::Router) -> Result<()> {
+    let Some(config) = Config::from_env() else {
+        axum::serve(listener, app.into_make_service()).await?;
+        return Ok(());
+    };
+
+    let builder = config.builder();
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(err) => {
+                // Errors such as hitting the file descriptor limit persist, so back off to avoid spinning.
+                tracing::warn!("Failed to accept connection: {err}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                continue;
+            }
+        };
+
+        let builder = builder.clone();
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(err) = connection.await {
+                tracing::debug!("Connection closed with an error: {err}");
+            }
+        });
+    }
+}
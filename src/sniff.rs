@@ -0,0 +1,37 @@
+/// Detects the content type of audio from its magic bytes, returning `None` for
+/// unrecognised formats such as raw PCM.
+pub fn content_type(audio: &[u8]) -> Option<&'static str> {
+    if audio.len() >= 12 && audio.starts_with(b"RIFF") && &audio[8..12] == b"WAVE" {
+        return Some("audio/wav");
+    }
+
+    if audio.starts_with(b"OggS") {
+        // The first page holds the codec identification header.
+        let first_page = &audio[..audio.len().min(128)];
+        return if contains(first_page, b"OpusHead") {
+            Some("audio/opus")
+        } else if contains(first_page, b"\x01vorbis") {
+            Some("audio/ogg")
+        } else {
+            None
+        };
+    }
+
+    if audio.starts_with(b"fLaC") {
+        return Some("audio/flac");
+    }
+
+    if audio.starts_with(b"ID3") || is_mp3_frame_sync(audio) {
+        return Some("audio/mpeg");
+    }
+
+    None
+}
+
+fn is_mp3_frame_sync(audio: &[u8]) -> bool {
+    matches!(audio, [0xFF, second, ..] if second & 0xE0 == 0xE0)
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    memchr::memmem::find(haystack, needle).is_some()
+}
@@ -0,0 +1,33 @@
+use tokio::io::AsyncWriteExt;
+
+use crate::Result;
+
+/// Spawns `command`, writing `input` to its stdin and returning its stdout.
+pub async fn pipe(mut command: tokio::process::Command, input: &[u8]) -> Result<Vec<u8>> {
+    let mut process = command
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = process.stdin.take().expect("Failed to open process stdin");
+    let input = input.to_vec();
+    let writer = tokio::spawn(async move {
+        stdin.write_all(&input).await?;
+        stdin.shutdown().await
+    });
+
+    let output = process.wait_with_output().await?;
+    writer.await??;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "{:?} failed: {}",
+            command.as_std().get_program(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(output.stdout)
+}
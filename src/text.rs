@@ -0,0 +1,313 @@
+use std::borrow::Cow;
+
+use crate::sentences;
+
+/// The maximum number of combining marks kept on a single character, anything past
+/// this is zalgo text that only slows down synthesis.
+const MAX_COMBINING_MARKS: u8 = 2;
+
+fn is_zero_width(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}' | '\u{00AD}'
+    )
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+fn is_removed_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\n' | '\t')
+}
+
+/// Strips characters which backends choke on or which are only used for abuse:
+/// control characters, zero-width and bidi control characters, and excess combining marks.
+pub fn normalize(text: &str) -> Cow<'_, str> {
+    let needs_normalizing = text.chars().any(|c| {
+        is_removed_control(c) || is_zero_width(c) || is_bidi_control(c) || is_combining_mark(c)
+    });
+
+    if !needs_normalizing {
+        return Cow::Borrowed(text);
+    }
+
+    let mut combining_marks = 0;
+    let mut normalized = String::with_capacity(text.len());
+    for c in text.chars() {
+        if is_zero_width(c) || is_bidi_control(c) {
+            continue;
+        }
+
+        if is_removed_control(c) {
+            normalized.push(' ');
+            continue;
+        }
+
+        if is_combining_mark(c) {
+            if combining_marks >= MAX_COMBINING_MARKS {
+                continue;
+            }
+
+            combining_marks += 1;
+        } else {
+            combining_marks = 0;
+        }
+
+        normalized.push(c);
+    }
+
+    Cow::Owned(normalized)
+}
+
+/// Separates every digit with a space, so numbers are read digit by digit by backends without SSML.
+pub fn space_digits(text: &str) -> Cow<'_, str> {
+    if !text
+        .as_bytes()
+        .windows(2)
+        .any(|w| w.iter().all(u8::is_ascii_digit))
+    {
+        return Cow::Borrowed(text);
+    }
+
+    let mut spaced = String::with_capacity(text.len() * 2);
+    let mut prev_digit = false;
+    for c in text.chars() {
+        let is_digit = c.is_ascii_digit();
+        if is_digit && prev_digit {
+            spaced.push(' ');
+        }
+
+        spaced.push(c);
+        prev_digit = is_digit;
+    }
+
+    Cow::Owned(spaced)
+}
+
+/// Escapes the characters which would otherwise be parsed as markup, so plain text can be put in SSML.
+pub fn escape_xml(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Wraps every run of digits with `<say-as interpret-as="digits">` for SSML backends.
+///
+/// The text must already be escaped with [`escape_xml`].
+pub fn ssml_digits(text: &str) -> String {
+    let mut wrapped = String::with_capacity(text.len());
+    let mut in_digits = false;
+    for c in text.chars() {
+        let is_digit = c.is_ascii_digit();
+        if is_digit && !in_digits {
+            wrapped.push_str("<say-as interpret-as=\"digits\">");
+        } else if !is_digit && in_digits {
+            wrapped.push_str("</say-as>");
+        }
+
+        wrapped.push(c);
+        in_digits = is_digit;
+    }
+
+    if in_digits {
+        wrapped.push_str("</say-as>");
+    }
+
+    wrapped
+}
+
+/// Lowercases the text and collapses its whitespace, so trivially different text can share a cache entry.
+pub fn cache_key(text: &str) -> String {
+    let mut key = String::with_capacity(text.len());
+    for word in text.split_whitespace() {
+        if !key.is_empty() {
+            key.push(' ');
+        }
+
+        key.extend(word.chars().flat_map(char::to_lowercase));
+    }
+
+    key
+}
+
+fn pack_chunks<'a>(
+    text: &'a str,
+    language: Option<&str>,
+    split_level: u8,
+    max_len: usize,
+    len: &impl Fn(&str) -> usize,
+    chunks: &mut Vec<&'a str>,
+) {
+    let pieces: Box<dyn Iterator<Item = &'a str>> = match split_level {
+        0 => Box::new(sentences::split(text, language)),
+        1 => Box::new(text.split_inclusive(char::is_whitespace)),
+        _ => Box::new(text.split_inclusive(|_| true)),
+    };
+
+    let mut start = 0;
+    let mut end = 0;
+    let mut chunk_len = 0;
+    for piece in pieces {
+        let piece_len = len(piece);
+        if chunk_len + piece_len > max_len && end > start {
+            chunks.push(&text[start..end]);
+            start = end;
+            chunk_len = 0;
+        }
+
+        if piece_len > max_len && split_level < 2 {
+            pack_chunks(piece, language, split_level + 1, max_len, len, chunks);
+            end += piece.len();
+            start = end;
+            continue;
+        }
+
+        end += piece.len();
+        chunk_len += piece_len;
+    }
+
+    if end > start {
+        chunks.push(&text[start..end]);
+    }
+}
+
+/// Splits the text into chunks no longer than `max_len` as measured by `len`, splitting between
+/// sentences of the `language` where possible, then between words, then between characters.
+pub fn split_chunks<'a>(
+    text: &'a str,
+    language: Option<&str>,
+    max_len: usize,
+    len: impl Fn(&str) -> usize,
+) -> Vec<&'a str> {
+    let mut chunks = Vec::new();
+    pack_chunks(text, language, 0, max_len, &len, &mut chunks);
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::{normalize, split_chunks, MAX_COMBINING_MARKS};
+
+    fn assert_chunks<'a>(text: &'a str, language: Option<&str>, max_len: usize) -> Vec<&'a str> {
+        let chunks = split_chunks(text, language, max_len, str::len);
+        assert_eq!(chunks.concat(), text);
+        for chunk in &chunks {
+            assert!(!chunk.is_empty());
+            assert!(chunk.len() <= max_len, "{chunk:?} is over {max_len} bytes");
+        }
+
+        chunks
+    }
+
+    #[test]
+    fn packs_sentences() {
+        assert_eq!(
+            assert_chunks("One. Two. Three. Four.", Some("en"), 10),
+            ["One. Two. ", "Three. ", "Four."]
+        );
+        assert_eq!(assert_chunks("Short.", None, 100), ["Short."]);
+    }
+
+    #[test]
+    fn splits_long_sentences_between_words() {
+        assert_eq!(
+            assert_chunks("a long sentence without any punctuation", None, 12),
+            ["a long ", "sentence ", "without any ", "punctuation"]
+        );
+    }
+
+    #[test]
+    fn never_exceeds_max_len() {
+        let text = "Das ist ein Satz mit Umlauten: äöü. Wörterwörterwörterwörter! 日本語のテキストです。長い長い長い長い文。";
+        for max_len in 4..=text.len() + 1 {
+            for language in [None, Some("de"), Some("ja")] {
+                assert_chunks(text, language, max_len);
+            }
+        }
+    }
+
+    #[test]
+    fn measures_with_len() {
+        let chars = |text: &str| text.chars().count();
+        let chunks = split_chunks("日本語。日本語。", Some("ja"), 4, chars);
+
+        assert_eq!(chunks, ["日本語。", "日本語。"]);
+    }
+
+    #[test]
+    fn normalize_borrows_clean_text() {
+        for text in [
+            "",
+            "Hello, world!",
+            "Line one\nLine\ttwo",
+            "日本語のテキスト",
+            "café",
+        ] {
+            assert!(
+                matches!(normalize(text), Cow::Borrowed(_)),
+                "{text:?} was copied"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_caps_zalgo() {
+        let zalgo = "Z\u{0351}\u{0308}\u{0300}\u{036B}a\u{1DC0}\u{20D0}\u{FE20}\u{1AB0}";
+        let normalized = normalize(zalgo);
+
+        assert_eq!(normalized, "Z\u{0351}\u{0308}a\u{1DC0}\u{20D0}");
+        for base in normalized.split(['Z', 'a']).skip(1) {
+            assert!(base.chars().count() <= usize::from(MAX_COMBINING_MARKS));
+        }
+    }
+
+    #[test]
+    fn normalize_strips_zero_width() {
+        assert_eq!(
+            normalize("a\u{200B}b\u{200C}c\u{200D}d\u{FEFF}e\u{00AD}f\u{2060}g"),
+            "abcdefg"
+        );
+    }
+
+    #[test]
+    fn normalize_strips_bidi_overrides() {
+        assert_eq!(
+            normalize("\u{202E}olleh\u{202C} \u{2066}a\u{2067}b\u{2068}c\u{2069}"),
+            "olleh abc"
+        );
+    }
+
+    #[test]
+    fn normalize_replaces_control_characters() {
+        assert_eq!(
+            normalize("a\u{0000}b\u{0007}c\u{001B}d\u{007F}e\rf\ng\th"),
+            "a b c d e f\ng\th"
+        );
+    }
+}
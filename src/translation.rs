@@ -1,9 +1,120 @@
-use std::marker::PhantomData;
+use std::{
+    future::Future,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use serde::ser::SerializeStruct;
 use small_fixed_array::FixedString;
 
+use crate::{str_to_fixedstring, QuotaExceeded};
+
+/// How long a key which ran out of quota is skipped for, while other keys are available.
+const EXHAUSTED_COOLDOWN: Duration = Duration::from_mins(10);
+
+struct Key {
+    token: FixedString<u8>,
+    /// When the key last ran out of quota.
+    exhausted_at: Mutex<Option<Instant>>,
+}
+
+impl Key {
+    fn is_exhausted(&self) -> bool {
+        self.exhausted_at
+            .lock()
+            .unwrap()
+            .is_some_and(|exhausted_at| exhausted_at.elapsed() < EXHAUSTED_COOLDOWN)
+    }
+}
+
+/// The `DeepL` keys, used round-robin to spread translations across their quotas.
+pub struct Keys {
+    inner: Box<[Key]>,
+    next: AtomicUsize,
+    active: AtomicUsize,
+}
+
+impl Keys {
+    fn read_env() -> Option<String> {
+        std::env::var("DEEPL_KEYS")
+            .or_else(|_| std::env::var("DEEPL_KEY"))
+            .ok()
+    }
+
+    /// If `DEEPL_KEYS` or `DEEPL_KEY` is set, without loading them.
+    pub fn is_configured() -> bool {
+        Self::read_env().is_some()
+    }
+
+    /// Reads the comma separated `DEEPL_KEYS`, falling back to the single `DEEPL_KEY`.
+    pub fn from_env() -> Option<Self> {
+        let keys: Box<[Key]> = Self::read_env()?
+            .split(',')
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(|token| Key {
+                token: str_to_fixedstring(token.to_owned()),
+                exhausted_at: Mutex::new(None),
+            })
+            .collect();
+
+        if keys.is_empty() {
+            return None;
+        }
+
+        tracing::info!("Loaded {} DeepL keys", keys.len());
+        Some(Self {
+            inner: keys,
+            next: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// The index of the key most recently used, for `/config`.
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Runs the request with the next key, moving on to the following keys if it is out of quota.
+    ///
+    /// Keys which recently ran out of quota are only tried once all of the others have.
+    async fn run<T, F>(&self, mut request: impl FnMut(FixedString<u8>) -> F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let (fresh, exhausted): (Vec<_>, Vec<_>) = (0..self.inner.len())
+            .map(|offset| (start + offset) % self.inner.len())
+            .partition(|&index| !self.inner[index].is_exhausted());
+
+        let mut last_err = None;
+        for index in fresh.into_iter().chain(exhausted) {
+            let key = &self.inner[index];
+            self.active.store(index, Ordering::Relaxed);
+
+            match request(key.token.clone()).await {
+                Err(err) if err.is::<QuotaExceeded>() => {
+                    tracing::warn!("DeepL key {index} has run out of quota");
+                    *key.exhausted_at.lock().unwrap() = Some(Instant::now());
+                    last_err = Some(err);
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| QuotaExceeded.into()))
+    }
+}
+
 fn deserialize_single_seq<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
 where
     T: serde::Deserialize<'de>,
@@ -36,8 +147,8 @@ struct TranslateRequest<'a> {
     preserve_formatting: u8,
 }
 
-#[derive(serde::Deserialize)]
-struct Translation {
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct Translation {
     pub text: FixedString,
     pub detected_source_language: FixedString<u8>,
 }
@@ -52,12 +163,32 @@ fn auth_header(token: &str) -> String {
     format!("DeepL-Auth-Key {token}")
 }
 
-pub async fn run(
+fn check_quota(resp: reqwest::Response) -> Result<reqwest::Response> {
+    // DeepL uses the non-standard 456 status code to signal that the quota has been used up.
+    if resp.status().as_u16() == 456 {
+        Err(QuotaExceeded.into())
+    } else {
+        Ok(resp.error_for_status()?)
+    }
+}
+
+/// Translates the content, returning the translation even if the content was already in `target_lang`.
+pub async fn translate(
+    reqwest: &reqwest::Client,
+    keys: &Keys,
+    content: &str,
+    target_lang: &str,
+) -> Result<Option<Translation>> {
+    keys.run(|token| async move { translate_with(reqwest, &token, content, target_lang).await })
+        .await
+}
+
+async fn translate_with(
     reqwest: &reqwest::Client,
     token: &str,
     content: &str,
     target_lang: &str,
-) -> Result<Option<FixedString>> {
+) -> Result<Option<Translation>> {
     let request = TranslateRequest {
         target_lang,
         text: content,
@@ -69,18 +200,31 @@ pub async fn run(
         .query(&request)
         .header("Authorization", auth_header(token))
         .send()
-        .await?
-        .error_for_status()?
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(check_quota)?
         .json()
         .await?;
 
-    if let Some(translation) = response.translations {
-        if translation.detected_source_language != target_lang {
-            return Ok(Some(translation.text));
-        }
-    }
+    Ok(response.translations)
+}
 
-    Ok(None)
+/// Translates the content, returning `None` if it was already in `target_lang` or one of
+/// the uppercase `skip_langs`.
+pub async fn run(
+    reqwest: &reqwest::Client,
+    keys: &Keys,
+    content: &str,
+    target_lang: &str,
+    skip_langs: &[String],
+) -> Result<Option<FixedString>> {
+    let translation = translate(reqwest, keys, content, target_lang).await?;
+    Ok(translation
+        .filter(|translation| {
+            let source_lang = translation.detected_source_language.as_str();
+            source_lang != target_lang && !skip_langs.iter().any(|lang| lang == source_lang)
+        })
+        .map(|translation| translation.text))
 }
 
 #[derive(serde::Deserialize)]
@@ -102,6 +246,14 @@ impl serde::Serialize for VoiceRequest {
 }
 
 pub async fn get_languages(
+    reqwest: &reqwest::Client,
+    keys: &Keys,
+) -> Result<Vec<(FixedString, FixedString)>> {
+    keys.run(|token| async move { get_languages_with(reqwest, &token).await })
+        .await
+}
+
+async fn get_languages_with(
     reqwest: &reqwest::Client,
     token: &str,
 ) -> Result<Vec<(FixedString, FixedString)>> {
@@ -110,8 +262,9 @@ pub async fn get_languages(
         .query(&VoiceRequest)
         .header("Authorization", auth_header(token))
         .send()
-        .await?
-        .error_for_status()?
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(check_quota)?
         .json()
         .await?;
 
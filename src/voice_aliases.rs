@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use small_fixed_array::FixedString;
+
+use crate::{Result, TTSMode};
+
+/// A stable name for a backend voice, so clients are not affected by the backend renaming it.
+#[derive(serde::Deserialize)]
+pub struct VoiceAlias {
+    /// The mode to use instead of the requested mode, if set.
+    #[serde(default)]
+    pub mode: Option<TTSMode>,
+    pub voice: FixedString<u8>,
+}
+
+pub type VoiceAliases = BTreeMap<String, VoiceAlias>;
+
+/// Loads the aliases from the JSON file at `VOICE_ALIASES_PATH`, or none if unset.
+pub fn load() -> Result<VoiceAliases> {
+    let Ok(path) = std::env::var("VOICE_ALIASES_PATH") else {
+        return Ok(VoiceAliases::new());
+    };
+
+    let aliases: VoiceAliases = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    tracing::info!("Loaded {} voice aliases", aliases.len());
+    Ok(aliases)
+}
+
+/// Returns the mode and voice to use, resolving the voice if it is an alias.
+pub fn resolve(
+    aliases: &VoiceAliases,
+    mode: TTSMode,
+    voice: FixedString<u8>,
+) -> (TTSMode, FixedString<u8>) {
+    match aliases.get(voice.as_str()) {
+        Some(alias) => (alias.mode.unwrap_or(mode), alias.voice.clone()),
+        None => (mode, voice),
+    }
+}
+
+/// The aliases which can be used with the mode, with the voice they resolve to.
+pub fn for_mode(
+    aliases: &VoiceAliases,
+    mode: TTSMode,
+) -> impl Iterator<Item = (&str, &VoiceAlias)> {
+    aliases
+        .iter()
+        .filter(move |(_, alias)| alias.mode.is_none_or(|alias_mode| alias_mode == mode))
+        .map(|(name, alias)| (name.as_str(), alias))
+}
@@ -0,0 +1,96 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwapOption;
+
+use crate::{parse_env, polly, RateLimited, Result};
+
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Connection failures, timeouts, rate limits, and server errors, which may succeed if retried,
+/// unlike errors such as a missing API key or an unparseable response.
+fn is_transient(err: &anyhow::Error) -> bool {
+    if err.is::<RateLimited>() {
+        return true;
+    }
+
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_connect()
+            || err.is_timeout()
+            || err.status().is_some_and(|status| {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            });
+    }
+
+    polly::is_transient_error(err)
+}
+
+/// Fetches the voices, retrying transient failures with backoff up to `VOICE_FETCH_RETRIES`
+/// times, so a blip during the first fetch does not fail every request waiting on it.
+async fn fetch_with_retries<T, Fut>(fetch: impl Fn() -> Fut) -> Result<T>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let max_retries: u8 = parse_env("VOICE_FETCH_RETRIES").unwrap_or(3);
+
+    let mut retry_delay = INITIAL_RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match fetch().await {
+            Ok(voices) => break Ok(voices),
+            Err(err) if retries < max_retries && is_transient(&err) => {
+                tracing::warn!("Failed to fetch voices, retrying in {retry_delay:?}: {err:?}");
+                tokio::time::sleep(retry_delay).await;
+
+                retries += 1;
+                retry_delay *= 2;
+            }
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+/// A lazily fetched voice list, which can be refreshed while requests are reading it.
+pub struct VoiceCache<T> {
+    voices: ArcSwapOption<T>,
+    fetch_lock: tokio::sync::Mutex<()>,
+}
+
+impl<T> VoiceCache<T> {
+    pub const fn new() -> Self {
+        Self {
+            voices: ArcSwapOption::const_empty(),
+            fetch_lock: tokio::sync::Mutex::const_new(()),
+        }
+    }
+
+    /// Returns the cached voices, fetching them if they have not been fetched yet.
+    pub async fn get<Fut>(&self, fetch: impl Fn() -> Fut) -> Result<Arc<T>>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        if let Some(voices) = self.voices.load_full() {
+            return Ok(voices);
+        }
+
+        let _guard = self.fetch_lock.lock().await;
+        if let Some(voices) = self.voices.load_full() {
+            return Ok(voices);
+        }
+
+        let voices = Arc::new(fetch_with_retries(fetch).await?);
+        self.voices.store(Some(voices.clone()));
+        Ok(voices)
+    }
+
+    /// Fetches the voices again, replacing the cached value if successful.
+    pub async fn refresh<Fut>(&self, fetch: impl Fn() -> Fut) -> Result<()>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let _guard = self.fetch_lock.lock().await;
+        let voices = fetch_with_retries(fetch).await?;
+
+        self.voices.store(Some(Arc::new(voices)));
+        Ok(())
+    }
+}
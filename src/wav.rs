@@ -0,0 +1,334 @@
+//! Helpers for reading and writing WAV files, see <http://soundfile.sapp.org/doc/WaveFormat/>
+
+use std::time::Duration;
+
+use crate::Result;
+
+const HEADER_LEN: usize = 44;
+
+/// The contents of the `fmt ` chunk.
+pub struct Format {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+/// The layout of a WAV file, found by walking its RIFF chunks.
+pub struct Wav {
+    pub format: Format,
+    /// The offset of the `data` chunk's size field, with the audio following it.
+    data_size_offset: usize,
+    pub data_len: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Parses the WAV file's chunks up to the audio, skipping any chunks such as `LIST`
+/// or `fact` which come before it, returning `None` if the file is malformed.
+pub fn parse(wav: &[u8]) -> Option<Wav> {
+    if wav.get(0..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut format = None;
+    let mut offset = 12;
+    loop {
+        let chunk_id = wav.get(offset..offset + 4)?;
+        let chunk_size = usize::try_from(read_u32(wav, offset + 4)?).ok()?;
+        let body_offset = offset + 8;
+
+        if chunk_id == b"data" {
+            // The size is left unset when written to a pipe, so the audio runs to the end of the file.
+            let available = wav.len() - body_offset.min(wav.len());
+            let data_len = if chunk_size == 0 || chunk_size > available {
+                available
+            } else {
+                chunk_size
+            };
+
+            return Some(Wav {
+                format: format?,
+                data_size_offset: offset + 4,
+                data_len,
+            });
+        }
+
+        if chunk_id == b"fmt " {
+            let body = wav.get(body_offset..body_offset.checked_add(chunk_size)?)?;
+            format = Some(Format {
+                channels: read_u16(body, 2)?,
+                sample_rate: read_u32(body, 4)?,
+                bits_per_sample: read_u16(body, 14)?,
+            });
+        }
+
+        // Chunks are padded to an even number of bytes.
+        offset = body_offset
+            .checked_add(chunk_size)?
+            .checked_add(chunk_size % 2)?;
+    }
+}
+
+impl Wav {
+    /// The range of the file which holds the audio.
+    pub fn data_range(&self) -> std::ops::Range<usize> {
+        let data_offset = self.data_size_offset + 4;
+        data_offset..data_offset + self.data_len
+    }
+
+    /// The length of the audio, or `None` if the format has no sample rate or sample size.
+    pub fn duration(&self) -> Option<Duration> {
+        let Format {
+            channels,
+            sample_rate,
+            bits_per_sample,
+        } = self.format;
+
+        let bytes_per_second =
+            u64::from(sample_rate) * u64::from(channels) * u64::from(bits_per_sample / 8);
+        if bytes_per_second == 0 {
+            return None;
+        }
+
+        let data_len = u64::try_from(self.data_len).ok()?;
+        Some(Duration::from_millis(data_len * 1000 / bytes_per_second))
+    }
+}
+
+/// Wraps headerless little-endian PCM audio in a WAV header.
+///
+/// Fails if the audio is too long to fit in a WAV file, which is limited to 4GiB.
+pub fn from_pcm(
+    pcm: &[u8],
+    sample_rate: u32,
+    channels: u16,
+    bits_per_sample: u16,
+) -> Result<Vec<u8>> {
+    let Some(data_len) = u32::try_from(pcm.len())
+        .ok()
+        .filter(|len| len.checked_add(36).is_some())
+    else {
+        anyhow::bail!("PCM data too long for a WAV file: {} bytes", pcm.len());
+    };
+
+    let block_align = channels * (bits_per_sample / 8);
+    let byte_rate = sample_rate * u32::from(block_align);
+
+    let mut wav = Vec::with_capacity(HEADER_LEN + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(data_len + 36).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16_u32.to_le_bytes()); // Subchunk1Size
+    wav.extend_from_slice(&1_u16.to_le_bytes()); // AudioFormat, 1 is PCM
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&bits_per_sample.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.extend_from_slice(pcm);
+    Ok(wav)
+}
+
+/// Fixes the wav header to set the RIFF chunk size and `data` chunk size, which are left unset
+/// by both mbrola and ffmpeg when writing to a pipe.
+///
+/// See: <https://github.com/hadware/voxpopuli/blob/fb94a6130c046bb9f7a27aaaed2a4b434666faa9/voxpopuli/main.py#L150-L158>
+///
+/// Fails if the audio is too long to fit in a WAV file, which is limited to 4GiB.
+pub fn fix_header(raw_wav: &mut [u8]) -> Result<()> {
+    let Some(wav) = parse(raw_wav) else {
+        tracing::warn!("Cannot fix the header of malformed WAV audio");
+        return Ok(());
+    };
+
+    let Ok(wav_len) = u32::try_from(raw_wav.len()) else {
+        anyhow::bail!("WAV data too long: {} bytes", raw_wav.len());
+    };
+
+    // `parse` found the data size field, so it is within the file.
+    let data_size_offset = wav.data_size_offset;
+    let data_len = wav_len - (data_size_offset as u32 + 4);
+
+    raw_wav[4..8].copy_from_slice(&(wav_len - 8).to_le_bytes());
+    raw_wav[data_size_offset..data_size_offset + 4].copy_from_slice(&data_len.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fix_header, from_pcm, parse};
+
+    const PCM: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+
+    /// A 16-bit mono WAV file with `extra_chunks` between the `fmt ` and `data` chunks.
+    fn wav_with_chunks(extra_chunks: &[(&[u8; 4], &[u8])], data_size: u32) -> Vec<u8> {
+        let mut wav = from_pcm(&[], 8000, 1, 16).unwrap();
+        wav.truncate(36);
+
+        for (id, body) in extra_chunks {
+            wav.extend_from_slice(*id);
+            wav.extend_from_slice(&u32::try_from(body.len()).unwrap().to_le_bytes());
+            wav.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                wav.push(0);
+            }
+        }
+
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_size.to_le_bytes());
+        wav.extend_from_slice(PCM);
+        wav
+    }
+
+    #[test]
+    fn from_pcm_round_trips() {
+        let raw = from_pcm(PCM, 22050, 2, 16).unwrap();
+        assert_eq!(raw.len(), 44 + PCM.len());
+
+        let wav = parse(&raw).unwrap();
+        assert_eq!(wav.format.channels, 2);
+        assert_eq!(wav.format.sample_rate, 22050);
+        assert_eq!(wav.format.bits_per_sample, 16);
+        assert_eq!(wav.data_len, PCM.len());
+        assert_eq!(&raw[wav.data_range()], PCM);
+    }
+
+    #[test]
+    fn parse_skips_chunks_before_data() {
+        let raw = wav_with_chunks(&[(b"LIST", b"INFOabcd"), (b"fact", &[10, 0, 0, 0])], 10);
+
+        let wav = parse(&raw).unwrap();
+        assert_eq!(wav.format.sample_rate, 8000);
+        assert_eq!(&raw[wav.data_range()], PCM);
+    }
+
+    #[test]
+    fn parse_skips_odd_chunk_padding() {
+        let raw = wav_with_chunks(&[(b"LIST", b"INFOa")], 10);
+
+        let wav = parse(&raw).unwrap();
+        assert_eq!(&raw[wav.data_range()], PCM);
+    }
+
+    #[test]
+    fn parse_clamps_data_size_to_buffer() {
+        for data_size in [0, 1000, u32::MAX] {
+            let raw = wav_with_chunks(&[], data_size);
+
+            let wav = parse(&raw).unwrap();
+            assert_eq!(wav.data_len, PCM.len());
+            assert_eq!(&raw[wav.data_range()], PCM);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_truncated_headers() {
+        let raw = from_pcm(PCM, 8000, 1, 16).unwrap();
+        for len in 0..44 {
+            assert!(parse(&raw[..len]).is_none(), "parsed a {len} byte header");
+        }
+
+        assert_eq!(parse(&raw[..44]).unwrap().data_len, 0);
+    }
+
+    #[test]
+    fn parse_rejects_missing_format() {
+        let mut raw = wav_with_chunks(&[], 10);
+        raw[12..16].copy_from_slice(b"junk");
+
+        assert!(parse(&raw).is_none());
+    }
+
+    #[test]
+    fn fix_header_sets_sizes() {
+        let mut raw = wav_with_chunks(&[(b"LIST", b"INFOa")], u32::MAX);
+        raw[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        fix_header(&mut raw).unwrap();
+
+        let riff_size = u32::from_le_bytes(raw[4..8].try_into().unwrap());
+        assert_eq!(riff_size as usize, raw.len() - 8);
+
+        let wav = parse(&raw).unwrap();
+        let data_size_offset = wav.data_range().start - 4;
+        let data_size = u32::from_le_bytes(
+            raw[data_size_offset..wav.data_range().start]
+                .try_into()
+                .unwrap(),
+        );
+        assert_eq!(data_size as usize, PCM.len());
+        assert_eq!(&raw[wav.data_range()], PCM);
+    }
+
+    #[test]
+    fn fix_header_ignores_malformed_audio() {
+        let mut raw = b"not a wav file".to_vec();
+        fix_header(&mut raw).unwrap();
+
+        assert_eq!(raw, b"not a wav file");
+    }
+
+    /// Checks malformed input is rejected or parsed within bounds, rather than panicking.
+    fn check_malformed(raw: &[u8]) {
+        if let Some(wav) = parse(raw) {
+            assert!(raw.get(wav.data_range()).is_some());
+            let _ = wav.duration();
+        }
+
+        let mut fixed = raw.to_vec();
+        fix_header(&mut fixed).unwrap();
+        if parse(raw).is_some() {
+            assert_eq!(parse(&fixed).unwrap().data_range().end, fixed.len());
+        } else {
+            assert_eq!(fixed, raw);
+        }
+    }
+
+    #[test]
+    fn mutated_headers_do_not_panic() {
+        use rand::{Rng, SeedableRng};
+
+        let valid = wav_with_chunks(&[(b"LIST", b"INFOa"), (b"fact", &[10, 0, 0, 0])], 10);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1213);
+
+        for _ in 0..10_000 {
+            let mut raw = valid.clone();
+            match rng.gen_range(0..4) {
+                0 => raw.truncate(rng.gen_range(0..valid.len())),
+                1 => {
+                    for _ in 0..rng.gen_range(1..8) {
+                        let index = rng.gen_range(0..raw.len());
+                        raw[index] = rng.gen();
+                    }
+                }
+                2 => {
+                    // Chunk sizes pointing past the end of the file, or overflowing offsets.
+                    let size: u32 = *[u32::MAX, u32::MAX - 1, 0x8000_0000, rng.gen()]
+                        .get(rng.gen_range(0..4))
+                        .unwrap();
+                    let offset = rng.gen_range(0..raw.len() - 4);
+                    raw[offset..offset + 4].copy_from_slice(&size.to_le_bytes());
+                }
+                _ => {
+                    raw = (0..rng.gen_range(0..128)).map(|_| rng.gen()).collect();
+                    if rng.gen() {
+                        raw.splice(0..0, *b"RIFF\0\0\0\0WAVE");
+                    }
+                }
+            }
+
+            check_malformed(&raw);
+        }
+    }
+}